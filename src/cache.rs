@@ -0,0 +1,135 @@
+//! Memory-mapped precompiled keymaps.
+//!
+//! Layout: `b"AIM1"`, entry count as `u32` LE, a table of `u32` LE offsets
+//! (entries sorted by sequence), then per entry `u16` sequence length,
+//! `u16` symbol length, and the two UTF-8 strings. Offsets are relative to
+//! the start of the file. Lookups binary-search the offset table straight on
+//! the mapped bytes, so loading a compiled keymap is essentially one `mmap`
+//! and instances across processes share the pages.
+
+use std::path::Path;
+
+pub const MAGIC: &[u8; 4] = b"AIM1";
+
+/// Serialize `entries` into the mapped format; the `compile` subcommand
+/// writes this next to the source keymap.
+pub fn encode(mut entries: Vec<(String, String)>) -> Vec<u8> {
+    entries.sort();
+    entries.dedup();
+    let mut out = MAGIC.to_vec();
+    out.extend((entries.len() as u32).to_le_bytes());
+    let mut blob = vec![];
+    let base = 8 + 4 * entries.len();
+    for (seq, sym) in &entries {
+        out.extend(((base + blob.len()) as u32).to_le_bytes());
+        blob.extend((seq.len() as u16).to_le_bytes());
+        blob.extend((sym.len() as u16).to_le_bytes());
+        blob.extend(seq.as_bytes());
+        blob.extend(sym.as_bytes());
+    }
+    out.extend(blob);
+    out
+}
+
+/// True when `cache` exists and is at least as new as `source`, i.e. safe
+/// to mmap instead of re-parsing the JSON.
+pub fn is_fresh(cache: &Path, source: &Path) -> bool {
+    let Ok(cache) = std::fs::metadata(cache).and_then(|m| m.modified()) else {
+        return false;
+    };
+    match std::fs::metadata(source).and_then(|m| m.modified()) {
+        Ok(source) => cache >= source,
+        // no source file at all (embedded-only setups): nothing to be stale against
+        Err(_) => true,
+    }
+}
+
+#[derive(Debug)]
+pub struct CompiledKeymap {
+    mmap: memmap2::Mmap,
+    count: usize,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(at..at + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(at..at + 2)?.try_into().ok()?,
+    ))
+}
+
+impl CompiledKeymap {
+    pub fn open(path: &Path) -> Option<Self> {
+        let file = std::fs::File::open(path).ok()?;
+        // safety: the file is treated as read-only; a concurrent writer can
+        // at worst make lookups return garbage strings, never UB on our side
+        let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+        if mmap.get(..4)? != MAGIC {
+            return None;
+        }
+        let count = read_u32(&mmap, 4)? as usize;
+        if 8 + 4 * count > mmap.len() {
+            return None;
+        }
+        Some(CompiledKeymap { mmap, count })
+    }
+
+    fn entry(&self, i: usize) -> Option<(&str, &str)> {
+        let at = read_u32(&self.mmap, 8 + 4 * i)? as usize;
+        let seq_len = read_u16(&self.mmap, at)? as usize;
+        let sym_len = read_u16(&self.mmap, at + 2)? as usize;
+        let seq = self.mmap.get(at + 4..at + 4 + seq_len)?;
+        let sym = self.mmap.get(at + 4 + seq_len..at + 4 + seq_len + sym_len)?;
+        Some((std::str::from_utf8(seq).ok()?, std::str::from_utf8(sym).ok()?))
+    }
+
+    /// All symbols whose sequence starts with `prefix`, exact matches first
+    /// (entries are sorted, so extensions follow the exact sequence).
+    pub fn lookup(&self, prefix: &str) -> Vec<String> {
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.entry(mid).map(|(s, _)| s < prefix).unwrap_or(true) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let start = lo;
+        (start..self.count)
+            .map_while(|i| self.entry(i))
+            .take_while(|(s, _)| s.starts_with(prefix))
+            .map(|(_, sym)| sym.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compiled_lookup() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join("aim-lsp-test-cache.bin");
+        std::fs::write(
+            &path,
+            encode(table(&[("Gl-", "ƛ"), ("Gl", "λ"), ("to", "→"), ("forall", "∀")])),
+        )?;
+        let compiled = CompiledKeymap::open(&path).unwrap();
+        assert_eq!(compiled.lookup("Gl"), vec!["λ", "ƛ"]);
+        assert_eq!(compiled.lookup("to"), vec!["→"]);
+        assert!(compiled.lookup("nope").is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Naive CJK input: syllable-keyed candidate tables.
+//!
+//! A table file is a JSON object mapping syllables to frequency-ordered
+//! candidate lists (`{"ni": ["你", "尼"], "hao": ["好", "号"]}`). Multi-
+//! syllable input is split greedily, taking the most frequent candidate for
+//! every syllable except the last.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct SyllableTable {
+    map: HashMap<String, Vec<String>>,
+}
+
+impl SyllableTable {
+    pub fn load(path: &Path) -> Option<Self> {
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+        let mut map = HashMap::new();
+        for (k, v) in json.as_object()? {
+            let candidates: Vec<String> = v
+                .as_array()?
+                .iter()
+                .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                .collect();
+            map.insert(k.clone(), candidates);
+        }
+        Some(SyllableTable { map })
+    }
+
+    /// Import a plain-text Cangjie code table (`.cin`-style lines of
+    /// `code<whitespace>character`; `#`/`%` lines are comments). Codes map to
+    /// every character sharing them, in file order.
+    pub fn load_cangjie(path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            if let (Some(code), Some(ch)) = (fields.next(), fields.next()) {
+                map.entry(code.to_lowercase()).or_default().push(ch.to_string());
+            }
+        }
+        Some(SyllableTable { map })
+    }
+
+    /// Frequency-ordered candidates for a syllable string.
+    pub fn candidates(&self, input: &str) -> Vec<String> {
+        if let Some(c) = self.map.get(input) {
+            return c.clone();
+        }
+        // greedy split: longest known syllable prefix, most frequent
+        // candidate for it, then complete the rest
+        for len in (1..input.len()).rev() {
+            let Some((head, rest)) = input.split_at_checked(len) else {
+                continue;
+            };
+            if let Some(first) = self.map.get(head).and_then(|c| c.first()) {
+                let tails = self.candidates(rest);
+                if !tails.is_empty() {
+                    return tails.into_iter().map(|t| format!("{}{}", first, t)).collect();
+                }
+            }
+        }
+        vec![]
+    }
+}
+
+/// Hepburn-ish romaji → hiragana table; longest match wins.
+const ROMAJI: &[(&str, &str)] = &[
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("sho", "しょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("cho", "ちょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("shi", "し"), ("chi", "ち"), ("tsu", "つ"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("sa", "さ"), ("si", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("ta", "た"), ("ti", "ち"), ("tu", "つ"), ("te", "て"), ("to", "と"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wo", "を"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("za", "ざ"), ("ji", "じ"), ("zi", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("n", "ん"), ("-", "ー"),
+];
+
+/// Deterministic romaji → kana transducer. Returns `None` when the input
+/// contains something the syllable grammar can't consume.
+pub fn romaji_to_kana(input: &str, katakana: bool) -> Option<String> {
+    let mut rest = input;
+    let mut out = String::new();
+    while !rest.is_empty() {
+        let mut chars = rest.chars();
+        let (a, b) = (chars.next()?, chars.next());
+        // doubled consonant marks a sokuon
+        if Some(a) == b && a != 'n' && !"aiueo".contains(a) {
+            out.push('っ');
+            rest = &rest[a.len_utf8()..];
+            continue;
+        }
+        let (r, kana) = ROMAJI
+            .iter()
+            .filter(|(r, _)| rest.starts_with(r))
+            .max_by_key(|(r, _)| r.len())?;
+        out.push_str(kana);
+        rest = &rest[r.len()..];
+    }
+    if katakana {
+        out = out
+            .chars()
+            .map(|c| match c as u32 {
+                0x3041..=0x3096 => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+                _ => c,
+            })
+            .collect();
+    }
+    Some(out)
+}
+
+/// Revised-romanization jamo, in Unicode composition order.
+const INITIALS: &[&str] = &[
+    "g", "kk", "n", "d", "tt", "r", "m", "b", "pp", "s", "ss", "", "j", "jj", "ch", "k", "t", "p",
+    "h",
+];
+const MEDIALS: &[&str] = &[
+    "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo", "we",
+    "wi", "yu", "eu", "ui", "i",
+];
+const FINALS: &[&str] = &[
+    "", "g", "kk", "gs", "n", "nj", "nh", "d", "l", "lg", "lm", "lb", "ls", "lt", "lp", "lh", "m",
+    "b", "bs", "s", "ss", "ng", "j", "ch", "k", "t", "p", "h",
+];
+
+fn match_longest(rest: &str, table: &[&str]) -> Option<(usize, usize)> {
+    table
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty() && rest.starts_with(**s))
+        .max_by_key(|(_, s)| s.len())
+        .map(|(i, s)| (i, s.len()))
+}
+
+/// Compose Hangul syllables from romanized jamo with the algorithmic
+/// composition rules (`annyeong` → 안녕).
+pub fn jamo_to_hangul(input: &str) -> Option<String> {
+    let mut rest = input;
+    let mut out = String::new();
+    while !rest.is_empty() {
+        // silent ieung when the syllable starts with a vowel
+        let (initial, ilen) = match_longest(rest, INITIALS).unwrap_or((11, 0));
+        let (medial, mlen) = match_longest(&rest[ilen..], MEDIALS)?;
+        let tail = &rest[ilen + mlen..];
+        // a trailing consonant is only a final if it doesn't start the next
+        // syllable (i.e. isn't followed by a vowel)
+        let mut finals: Vec<(usize, usize)> = FINALS
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.is_empty() && tail.starts_with(**s))
+            .map(|(i, s)| (i, s.len()))
+            .collect();
+        finals.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+        let (fin, flen) = finals
+            .into_iter()
+            .find(|(_, len)| {
+                let after = &tail[*len..];
+                after.is_empty() || match_longest(after, MEDIALS).is_none()
+            })
+            .unwrap_or((0, 0));
+        let code = 0xAC00 + ((initial * 21 + medial) * 28 + fin) as u32;
+        out.push(char::from_u32(code)?);
+        rest = &tail[flen..];
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table() -> SyllableTable {
+        let mut map = HashMap::new();
+        map.insert("ni".to_string(), vec!["你".to_string(), "尼".to_string()]);
+        map.insert("hao".to_string(), vec!["好".to_string(), "号".to_string()]);
+        SyllableTable { map }
+    }
+
+    #[test]
+    fn test_romaji_to_kana() {
+        assert_eq!(
+            romaji_to_kana("konnichiha", false),
+            Some("こんにちは".to_string())
+        );
+        assert_eq!(romaji_to_kana("kitte", false), Some("きって".to_string()));
+        assert_eq!(romaji_to_kana("ramen", true), Some("ラメン".to_string()));
+        assert_eq!(romaji_to_kana("xq", false), None);
+    }
+
+    #[test]
+    fn test_jamo_to_hangul() {
+        assert_eq!(jamo_to_hangul("annyeong"), Some("안녕".to_string()));
+        assert_eq!(jamo_to_hangul("hana"), Some("하나".to_string()));
+        assert_eq!(jamo_to_hangul("krx"), None);
+    }
+
+    #[test]
+    fn test_load_cangjie() {
+        let dir = std::env::temp_dir().join("aim-lsp-cangjie-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("table.txt");
+        std::fs::write(&path, "# comment\noiar 命\na 日\nA 曰\n").unwrap();
+        let table = SyllableTable::load_cangjie(&path).unwrap();
+        assert_eq!(table.candidates("oiar"), vec!["命"]);
+        assert_eq!(table.candidates("a"), vec!["日", "曰"]);
+    }
+
+    #[test]
+    fn test_syllable_candidates() {
+        assert_eq!(table().candidates("ni"), vec!["你", "尼"]);
+        assert_eq!(table().candidates("nihao"), vec!["你好", "你号"]);
+        assert!(table().candidates("xyz").is_empty());
+    }
+}
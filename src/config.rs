@@ -0,0 +1,500 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::DocumentFilter;
+
+/// Server settings, read from `initializationOptions`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// Keymap file for this session, replacing the one found at startup.
+    /// Editors rarely launch the server with a useful working directory, so
+    /// this is the reliable way to point it at a keymap.
+    pub keymap_path: Option<PathBuf>,
+    /// Ordered keymap layers (system, user, workspace, …) merged over the
+    /// active keymap on initialize; later files override or extend earlier
+    /// ones, and conflicting sequences are logged.
+    pub keymap_files: Vec<PathBuf>,
+    /// Globs (relative to each workspace root) selecting the files that
+    /// workspace-wide conversion touches.
+    pub convert_globs: Vec<String>,
+    /// Globs for files whose escape sequences get expanded on save.
+    pub expand_on_save: Vec<String>,
+    /// Expand remaining sequences right before every save, regardless of
+    /// the `expandOnSave` globs — no stray `\to` ever hits the disk.
+    pub convert_on_save: bool,
+    /// Restrict completion to documents matching these filters (scheme,
+    /// language, pattern). When set and the client supports dynamic
+    /// registration, completion is registered with this selector instead of
+    /// applying to every document.
+    pub document_selector: Option<Vec<DocumentFilter>>,
+    /// Keyboard rows used for adjacent-key typo tolerance; `None` disables
+    /// the fallback.
+    pub keyboard_layout: Option<Vec<String>>,
+    /// Match sequences case-insensitively (case-exact matches still rank
+    /// first).
+    pub case_insensitive: bool,
+    /// The character starting an escape sequence (backslash by default);
+    /// set to e.g. `;` where the backslash already means something.
+    pub trigger: String,
+    /// Extra triggers, each bound to its own keymap files — e.g. `:` →
+    /// emoji shortcodes and `;` → digraphs next to the backslash math
+    /// symbols. A prefix after one of these completes from its own trie
+    /// only, never the main keymap.
+    pub triggers: HashMap<String, Vec<PathBuf>>,
+    /// Treat a doubled trigger (`\\`) as an escaped literal and offer no
+    /// completion after it; disable for keymaps where `\\` is a sequence.
+    pub escape_doubled_trigger: bool,
+    /// Enable the `:shortcode:` emoji mode: `:` becomes a trigger bound to
+    /// the bundled shortcode table (files bound to `:` in `triggers` merge
+    /// over it), and typing the closing `:` expands on the spot.
+    pub emoji: bool,
+    /// Characters besides whitespace that end a sequence token; a prefix
+    /// crossing a boundary is prose, not a pending sequence, and offers no
+    /// completion. Empty by default — most keymaps only need whitespace.
+    pub boundary_chars: String,
+    /// Only complete inside math context — `$...$`, `$$...$$` or a math
+    /// environment — for LaTeX/Markdown sources where symbols belong in
+    /// formulas. Off by default.
+    pub math_mode_only: bool,
+    /// Regexes gating completion by context: when any are set, at least one
+    /// must match the cursor's line — e.g. `--.*` to complete only inside
+    /// line comments.
+    pub context_include: Vec<String>,
+    /// Regexes suppressing completion: a match on the cursor's line (say, a
+    /// string-literal pattern) means no candidates at that position.
+    pub context_exclude: Vec<String>,
+    /// Expand a complete, unambiguous sequence the moment a terminator
+    /// (space or punctuation) is typed after it, via `workspace/applyEdit` —
+    /// no completion popup involved.
+    pub auto_expand: bool,
+    /// When the strict trie finds nothing, also try substring/subsequence
+    /// matches on sequences and matches on symbols' Unicode names.
+    pub fuzzy_matching: bool,
+    /// When nothing else matches, search official Unicode character names
+    /// (`\black-star` → ★, `\snowman` → ☃), so any character is reachable
+    /// even when no keymap names it. Builds a full names table on first use.
+    pub unicode_name_search: bool,
+    /// Template for completion labels; placeholders `{seq}`, `{sym}`,
+    /// `{name}`, `{codepoint}`. A value without placeholders names a
+    /// preset: `screenReader` (spoken Unicode name first) or `verbose`.
+    pub label_template: String,
+    /// Optional template for completion item details, same placeholders.
+    pub detail_template: Option<String>,
+    /// Normalize inserted symbols to `"nfc"` or `"nfd"` before building
+    /// edits; toolchains disagree about composed vs. decomposed forms and
+    /// mixing them in one file causes spurious diffs. Unset inserts keymap
+    /// values as-is.
+    pub normalization: Option<String>,
+    /// Unicode block names the editor's font is known to cover; when set,
+    /// candidates with any character outside these blocks are hidden (e.g.
+    /// drop the astral math alphanumerics a terminal font can't render).
+    pub allowed_blocks: Vec<String>,
+    /// Cap on candidates returned per completion request.
+    pub max_candidates: usize,
+    /// Which output spelling completion inserts: `unicode` (the symbol
+    /// itself) or an alternate flavor declared on entries (`latex`,
+    /// `html`, ...); symbols without that flavor fall back to unicode.
+    pub output_flavor: String,
+    /// Treat a bare trigger as a symbol browser: an empty prefix lists the
+    /// top of the keymap (most-used sequences first, then shortest) up to
+    /// the result cap, instead of offering nothing. For discovering what
+    /// the keymap holds without knowing any sequence yet.
+    pub browse_on_bare_trigger: bool,
+    /// Also offer the typed text itself as the last completion item, so
+    /// accepting it keeps e.g. the LaTeX macro `\alpha` where the glyph
+    /// isn't wanted, without having to dismiss the popup carefully.
+    pub offer_literal: bool,
+    /// Always answer with plain `insertText` instead of a `textEdit`, for
+    /// clients that ignore edits and would otherwise insert the item label
+    /// verbatim. Normally derived from the advertised capabilities; this
+    /// forces it for clients that advertise more than they honor.
+    pub insert_text_fallback: bool,
+    /// Declarative sequence families stamped out into trie entries when the
+    /// keymap is built — subscripts, superscripts, bold/italic math
+    /// alphabets — instead of listing every member by hand. See
+    /// [`crate::keymap::Family`].
+    pub families: Vec<crate::keymap::Family>,
+    /// Named keymap profiles ("agda", "lean", "emoji", ...): extra keymap
+    /// files layered on top of the active keymap while the profile is
+    /// selected. Switch at runtime with the `aim.setProfile` command.
+    pub profiles: HashMap<String, Vec<PathBuf>>,
+    /// Profile active at startup.
+    pub profile: Option<String>,
+    /// Restrict the server to documents with these languageIds (as reported
+    /// in `didOpen`); empty means every document. Keeps symbol completion
+    /// out of, say, JSON files without per-client selector support.
+    pub languages: Vec<String>,
+    /// Keymap files per languageId (e.g. `agda` → `["agda.json"]`); documents
+    /// in these languages use their own keymap set instead of the global one.
+    pub language_keymaps: HashMap<String, Vec<PathBuf>>,
+    /// Ordered chain of keymap files tried when the active keymap has no
+    /// match for a prefix (personal overrides → global → bundled).
+    pub fallback_keymaps: Vec<PathBuf>,
+    /// Report every structural problem in loaded keymap files (bad value
+    /// shapes, duplicate definitions, missing sub-files) with its path into
+    /// the JSON, instead of silently dropping the entries involved.
+    pub strict_keymap: bool,
+    /// Sequences removed from the merged keymap after every layer has
+    /// loaded — a blacklist for built-ins that conflict with text the user
+    /// legitimately types, without forking the whole keymap.
+    pub disabled_sequences: Vec<String>,
+    /// Sequence prefix for entries imported from Vim digraph tables
+    /// (`\d` + digraph with the default).
+    pub digraph_prefix: String,
+    /// Pinyin syllable table file; enables the pinyin input mode.
+    pub pinyin_table: Option<PathBuf>,
+    /// Leader after the trigger selecting pinyin mode (`\py:nihao`).
+    pub pinyin_leader: String,
+    /// Zhuyin syllable table file (keyed by bopomofo keystrokes).
+    pub zhuyin_table: Option<PathBuf>,
+    /// Leader after the trigger selecting zhuyin mode (`\zy:ㄋㄧˇ`).
+    pub zhuyin_leader: String,
+    /// Cangjie code table file (plain-text `.cin`-style, imported at first
+    /// use of the leader).
+    pub cangjie_table: Option<PathBuf>,
+    /// Leader after the trigger selecting cangjie mode (`\cj:oiar`).
+    pub cangjie_leader: String,
+    /// Leader for the Greek transliteration mode (`\Ga` → α, `\Gth` → θ);
+    /// empty disables it.
+    pub greek_leader: String,
+    /// Leader for romaji → hiragana conversion (`\jp:konnichiha`).
+    pub romaji_leader: String,
+    /// Leader for romaji → katakana conversion.
+    pub katakana_leader: String,
+    /// Leader for romanized jamo → Hangul composition (`\kr:annyeong`).
+    pub hangul_leader: String,
+    /// Delay in milliseconds before lints are recomputed after an edit.
+    pub diagnostics_delay_ms: u64,
+    /// Log verbosity: `off` silences the informational client log messages.
+    pub log_level: String,
+    /// Per-rule lint levels.
+    pub diagnostic_rules: DiagnosticRules,
+    /// Strings whose appearance in a document is flagged by the denylist
+    /// rule (e.g. symbols a team has banned from its sources).
+    pub denylist: Vec<String>,
+}
+
+/// Lint level per diagnostic rule: `"off"`, `"hint"`, `"info"`, `"warning"`
+/// or `"error"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticRules {
+    pub unexpanded_sequence: String,
+    pub unknown_sequence: String,
+    pub confusable: String,
+    pub bidi_control: String,
+    pub denylist: String,
+}
+
+impl Default for DiagnosticRules {
+    fn default() -> Self {
+        DiagnosticRules {
+            unexpanded_sequence: "hint".to_string(),
+            unknown_sequence: "warning".to_string(),
+            confusable: "warning".to_string(),
+            bidi_control: "warning".to_string(),
+            denylist: "error".to_string(),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            keymap_path: None,
+            keymap_files: vec![],
+            convert_globs: vec!["**/*.agda".to_string()],
+            expand_on_save: vec![],
+            convert_on_save: false,
+            document_selector: None,
+            keyboard_layout: Some(crate::fuzzy::QWERTY.iter().map(|r| r.to_string()).collect()),
+            case_insensitive: false,
+            trigger: "\\".to_string(),
+            triggers: HashMap::new(),
+            escape_doubled_trigger: true,
+            emoji: false,
+            boundary_chars: String::new(),
+            math_mode_only: false,
+            context_include: vec![],
+            context_exclude: vec![],
+            auto_expand: false,
+            fuzzy_matching: false,
+            unicode_name_search: false,
+            label_template: "{seq} {sym}".to_string(),
+            detail_template: None,
+            normalization: None,
+            allowed_blocks: vec![],
+            max_candidates: 50,
+            output_flavor: "unicode".to_string(),
+            browse_on_bare_trigger: false,
+            offer_literal: false,
+            insert_text_fallback: false,
+            families: vec![],
+            profiles: HashMap::new(),
+            profile: None,
+            languages: vec![],
+            language_keymaps: HashMap::new(),
+            fallback_keymaps: vec![],
+            strict_keymap: false,
+            disabled_sequences: vec![],
+            digraph_prefix: "d".to_string(),
+            pinyin_table: None,
+            pinyin_leader: "py:".to_string(),
+            zhuyin_table: None,
+            zhuyin_leader: "zy:".to_string(),
+            cangjie_table: None,
+            cangjie_leader: "cj:".to_string(),
+            greek_leader: "G".to_string(),
+            romaji_leader: "jp:".to_string(),
+            katakana_leader: "jpk:".to_string(),
+            hangul_leader: "kr:".to_string(),
+            diagnostics_delay_ms: 300,
+            log_level: "info".to_string(),
+            diagnostic_rules: DiagnosticRules::default(),
+            denylist: vec![],
+        }
+    }
+}
+
+impl Settings {
+    pub fn new(json: Option<serde_json::Value>) -> Self {
+        Self::resolve(json, None)
+    }
+
+    /// Resolve settings from every configuration source. Precedence, lowest
+    /// first: built-in defaults, the user config file (`AIM_CONFIG` or
+    /// `~/.config/naive-input/config.toml`), environment variables, `--set`
+    /// CLI flags, `initializationOptions`, then `workspace/configuration`
+    /// overlays. Objects merge key-wise; scalars and arrays from later
+    /// layers replace earlier ones, except `AIM_KEYMAP_SET` which appends
+    /// to the fallback chain.
+    pub fn resolve(
+        init_options: Option<serde_json::Value>,
+        workspace: Option<serde_json::Value>,
+    ) -> Self {
+        let env = Env::load();
+        let args: Vec<String> = std::env::args().collect();
+        let layers = Layers {
+            user_file: env
+                .config
+                .as_deref()
+                .and_then(file_layer)
+                .or_else(|| file_layer(&config_dir()?.join("config.toml"))),
+            env: env.as_layer(),
+            cli: cli_layer(&args),
+            init_options,
+            workspace,
+        };
+        let mut settings = layers.merged();
+        settings.fallback_keymaps.extend(env.keymap_set);
+        settings
+    }
+}
+
+/// The configuration layers, lowest precedence first. One resolution path
+/// for everything config-dependent, instead of each feature consulting its
+/// own source.
+#[derive(Debug, Default)]
+pub struct Layers {
+    pub user_file: Option<serde_json::Value>,
+    pub env: Option<serde_json::Value>,
+    pub cli: Option<serde_json::Value>,
+    pub init_options: Option<serde_json::Value>,
+    pub workspace: Option<serde_json::Value>,
+}
+
+impl Layers {
+    /// Merge the layers in precedence order and deserialize the result;
+    /// anything no layer mentions keeps its built-in default.
+    pub fn merged(self) -> Settings {
+        let mut merged = serde_json::Value::Object(Default::default());
+        for layer in [
+            self.user_file,
+            self.env,
+            self.cli,
+            self.init_options,
+            self.workspace,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            merge_value(&mut merged, layer);
+        }
+        serde_json::from_value(merged).unwrap_or_default()
+    }
+}
+
+/// Deep-merge `over` into `base`: objects merge key-wise recursively, any
+/// other value (arrays included) replaces the base wholesale.
+fn merge_value(base: &mut serde_json::Value, over: serde_json::Value) {
+    match (base, over) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(over)) => {
+            for (key, value) in over {
+                merge_value(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Parse a settings file as a layer: TOML by extension, JSON otherwise.
+/// Both carry the same shape as `initializationOptions`.
+fn file_layer(path: &Path) -> Option<serde_json::Value> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    if path.extension().is_some_and(|e| e == "toml") {
+        serde_json::to_value(toml::from_str::<toml::Value>(&raw).ok()?).ok()
+    } else {
+        serde_json::from_str(&raw).ok()
+    }
+}
+
+/// Repeated `--set dotted.path=value` CLI flags as a layer; values parse as
+/// JSON, falling back to plain strings.
+fn cli_layer(args: &[String]) -> Option<serde_json::Value> {
+    let mut layer = serde_json::Value::Object(Default::default());
+    let mut any = false;
+    for i in 0..args.len() {
+        if args[i] == "--set"
+            && let Some((path, raw)) = args.get(i + 1).and_then(|kv| kv.split_once('='))
+        {
+            let value = serde_json::from_str(raw)
+                .unwrap_or_else(|_| serde_json::Value::String(raw.to_string()));
+            let nested = path
+                .split('.')
+                .rev()
+                .fold(value, |v, key| serde_json::json!({ key: v }));
+            merge_value(&mut layer, nested);
+            any = true;
+        }
+    }
+    any.then_some(layer)
+}
+
+/// The platform config directory: `$XDG_CONFIG_HOME/naive-input`, falling
+/// back to `~/.config/naive-input` (or `%APPDATA%\naive-input` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+            }
+        })
+        .map(|dir| dir.join("naive-input"))
+}
+
+/// The platform data directory for state files (usage statistics):
+/// `$XDG_DATA_HOME/naive-input`, falling back to `~/.local/share/naive-input`
+/// (or `%APPDATA%\naive-input` on Windows).
+pub fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            }
+        })
+        .map(|dir| dir.join("naive-input"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_toml_config() {
+        let dir = std::env::temp_dir().join("aim-lsp-config-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(
+            &path,
+            "pinyinLeader = \"p!\"\ncaseInsensitive = true\n\n[diagnosticRules]\nconfusable = \"off\"\n",
+        )
+        .unwrap();
+        let settings = Layers {
+            user_file: file_layer(&path),
+            ..Default::default()
+        }
+        .merged();
+        assert_eq!(settings.pinyin_leader, "p!");
+        assert!(settings.case_insensitive);
+        assert_eq!(settings.diagnostic_rules.confusable, "off");
+        // untouched fields keep their defaults
+        assert_eq!(settings.romaji_leader, "jp:");
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let settings = Layers {
+            user_file: Some(serde_json::json!({ "pinyinLeader": "low", "logLevel": "debug" })),
+            cli: Some(serde_json::json!({ "pinyinLeader": "mid" })),
+            init_options: Some(serde_json::json!({ "pinyinLeader": "high" })),
+            ..Default::default()
+        }
+        .merged();
+        assert_eq!(settings.pinyin_leader, "high");
+        // keys only a lower layer mentions survive the merge
+        assert_eq!(settings.log_level, "debug");
+    }
+}
+
+/// Environment overrides: the easiest configuration channel for editor
+/// plugins that can only set env vars on the spawned server process.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    /// `AIM_KEYMAP`: path of the main keymap file.
+    pub keymap: Option<PathBuf>,
+    /// `AIM_KEYMAP_SET`: extra keymap files (platform path separator)
+    /// appended to the fallback chain.
+    pub keymap_set: Vec<PathBuf>,
+    /// `AIM_LOG`: log verbosity.
+    pub log: Option<String>,
+    /// `AIM_CONFIG`: path of a server-level settings file.
+    pub config: Option<PathBuf>,
+}
+
+impl Env {
+    /// This environment as a settings layer. `AIM_KEYMAP` and
+    /// `AIM_KEYMAP_SET` have dedicated handling outside the merge.
+    fn as_layer(&self) -> Option<serde_json::Value> {
+        self.log
+            .as_ref()
+            .map(|log| serde_json::json!({ "logLevel": log }))
+    }
+
+    pub fn load() -> Self {
+        Env {
+            keymap: std::env::var_os("AIM_KEYMAP").map(PathBuf::from),
+            keymap_set: std::env::var("AIM_KEYMAP_SET")
+                .ok()
+                .map(|s| std::env::split_paths(&s).collect())
+                .unwrap_or_default(),
+            log: std::env::var("AIM_LOG").ok(),
+            config: std::env::var_os("AIM_CONFIG").map(PathBuf::from),
+        }
+    }
+
+    /// The main keymap file: `AIM_KEYMAP`, then `keymap.json` (or `.toml`)
+    /// in the config directory, then `keymap.json` in the working directory
+    /// as always — so the server is usable no matter where the editor
+    /// launched it from.
+    pub fn keymap_path(&self) -> PathBuf {
+        if let Some(path) = &self.keymap {
+            return path.clone();
+        }
+        if let Some(dir) = config_dir() {
+            for name in ["keymap.json", "keymap.toml"] {
+                let candidate = dir.join(name);
+                if candidate.is_file() {
+                    return candidate;
+                }
+            }
+        }
+        PathBuf::from("keymap.json")
+    }
+}
@@ -0,0 +1,107 @@
+//! Cursor-context detection for documents where symbols only belong in
+//! certain spots — e.g. LaTeX and Markdown, where a `\alpha` outside a
+//! formula is prose, not math.
+
+/// LaTeX environments whose body is in math mode.
+const MATH_ENVS: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "eqnarray",
+    "math",
+    "displaymath",
+];
+
+/// Whether the byte offset `at` of `text` sits inside `$...$`, `$$...$$`
+/// or a math environment. The scan is naive — delimiters are counted from
+/// the start of the document to the cursor — which is plenty for
+/// well-formed sources and degrades gracefully on broken ones.
+pub fn in_math(text: &str, at: usize) -> bool {
+    let head = &text[..at.min(text.len())];
+    let chars: Vec<char> = head.chars().collect();
+    let mut dollars = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            // `\$` is a literal dollar, and this also skips over commands
+            '\\' => i += 1,
+            '$' => {
+                // `$$` opens or closes display math as a single delimiter
+                if chars.get(i + 1) == Some(&'$') {
+                    i += 1;
+                }
+                dollars += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if dollars % 2 == 1 {
+        return true;
+    }
+    MATH_ENVS.iter().any(|env| {
+        head.matches(&format!("\\begin{{{}}}", env)).count()
+            > head.matches(&format!("\\end{{{}}}", env)).count()
+    })
+}
+
+/// Evaluate user-defined regex filters against the cursor's line: with any
+/// `include` patterns configured at least one must match, and no `exclude`
+/// pattern may. Patterns that fail to compile are skipped.
+pub fn line_allowed(line: &str, include: &[String], exclude: &[String]) -> bool {
+    let compile = |p: &String| regex::Regex::new(p).ok();
+    if !include.is_empty()
+        && !include
+            .iter()
+            .filter_map(compile)
+            .any(|re| re.is_match(line))
+    {
+        return false;
+    }
+    !exclude
+        .iter()
+        .filter_map(compile)
+        .any(|re| re.is_match(line))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_in_math() {
+        let text = "prose $x + y$ prose\n$$\na + b\n$$\nafter\n";
+        let at = |pat: &str| text.find(pat).unwrap();
+        assert!(in_math(text, at("x +")));
+        assert!(!in_math(text, at("prose $")));
+        assert!(!in_math(text, at(" prose\n")));
+        assert!(in_math(text, at("a + b")));
+        assert!(!in_math(text, at("after")));
+        // escaped dollars are literals
+        assert!(!in_math("costs \\$5 and", 12));
+        // environments count too
+        let env = "\\begin{align}\nx &= y\n\\end{align}\ndone\n";
+        assert!(in_math(env, env.find("x &=").unwrap()));
+        assert!(!in_math(env, env.find("done").unwrap()));
+    }
+
+    #[test]
+    fn test_line_allowed() {
+        let include = vec!["--.*".to_string()];
+        let exclude = vec!["\"".to_string()];
+        // no filters: everything passes
+        assert!(line_allowed("x = y", &[], &[]));
+        // include: only lines with a `--` comment
+        assert!(line_allowed("x = y -- \\to here", &include, &[]));
+        assert!(!line_allowed("x = y", &include, &[]));
+        // exclude: no completion on lines with a string literal
+        assert!(!line_allowed("s = \"str\" ++ \\al", &[], &exclude));
+        assert!(line_allowed("x = y \\al", &[], &exclude));
+        // invalid patterns are skipped rather than blocking completion
+        assert!(line_allowed("x", &[], &["[".to_string()]));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::Keymap;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::*;
+
+/// A single escape sequence found in a document, with the symbol it would
+/// expand to. Positions are in characters, consistent with `completion`.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub line: u32,
+    pub start: u32,
+    pub end: u32,
+    pub sequence: String,
+    pub symbol: String,
+}
+
+/// Find every `\sequence` in `text` that the keymap can expand, taking the
+/// longest match at each trigger and its first candidate symbol.
+pub fn scan(keymap: &Keymap, text: &str) -> Vec<Replacement> {
+    scan_with(keymap, text, '\\')
+}
+
+/// `scan` with a configurable trigger character instead of the backslash.
+pub fn scan_with(keymap: &Keymap, text: &str, trigger: char) -> Vec<Replacement> {
+    let mut ret = vec![];
+    for (ln, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == trigger {
+                let rest: String = chars[i + 1..].iter().collect();
+                if let Some((len, symbols)) = keymap.longest_match(&rest) {
+                    ret.push(Replacement {
+                        line: ln as u32,
+                        start: i as u32,
+                        end: (i + 1 + len) as u32,
+                        sequence: chars[i + 1..i + 1 + len].iter().collect(),
+                        symbol: symbols[0].to_string(),
+                    });
+                    i += 1 + len;
+                    continue;
+                }
+            }
+            i += 1;
+        }
+    }
+    ret
+}
+
+/// Apply replacements to `text`, returning the converted document. Line
+/// endings are kept as they were, so CRLF files stay CRLF.
+pub fn apply(text: &str, replacements: &[Replacement]) -> String {
+    text.split_inclusive('\n')
+        .enumerate()
+        .map(|(ln, line)| {
+            let ending = if line.ends_with("\r\n") {
+                "\r\n"
+            } else if line.ends_with('\n') {
+                "\n"
+            } else {
+                ""
+            };
+            let body = &line[..line.len() - ending.len()];
+            let mut chars: Vec<char> = body.chars().collect();
+            for r in replacements.iter().rev() {
+                if r.line == ln as u32 {
+                    chars.splice(r.start as usize..r.end as usize, r.symbol.chars());
+                }
+            }
+            chars.into_iter().collect::<String>() + ending
+        })
+        .collect()
+}
+
+/// Unified diff of a conversion. Replacement values can span lines, so the
+/// two sides may disagree on line count; a longest-common-subsequence walk
+/// keeps unchanged lines aligned instead of pairing them up by index.
+pub fn diff(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // suffix LCS lengths over lines; conversions touch a small fraction of
+    // the document, so the quadratic table stays affordable
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut out = String::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        // a run of changed lines becomes one hunk, ending where the
+        // two sides realign
+        let (at_a, at_b) = (i, j);
+        let mut minus = vec![];
+        let mut plus = vec![];
+        while (i < a.len() || j < b.len()) && !(i < a.len() && j < b.len() && a[i] == b[j]) {
+            if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+                plus.push(b[j]);
+                j += 1;
+            } else {
+                minus.push(a[i]);
+                i += 1;
+            }
+        }
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            at_a + 1,
+            minus.len(),
+            at_b + 1,
+            plus.len()
+        ));
+        for l in minus {
+            out.push_str(&format!("-{}\n", l));
+        }
+        for l in plus {
+            out.push_str(&format!("+{}\n", l));
+        }
+    }
+    out
+}
+
+pub fn build_globset(patterns: &[String]) -> Option<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for p in patterns {
+        builder.add(Glob::new(p).ok()?);
+    }
+    builder.build().ok()
+}
+
+/// Recursively collect the files under `root` whose relative path matches
+/// `globs`, skipping anything excluded by `.gitignore` or `.aimignore`.
+pub fn collect_files(root: &Path, globs: &GlobSet) -> Vec<PathBuf> {
+    let mut builder = ignore::WalkBuilder::new(root);
+    builder.add_custom_ignore_filename(".aimignore");
+    builder
+        .build()
+        .flatten()
+        .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .map(|e| e.into_path())
+        .filter(|p| {
+            p.strip_prefix(root)
+                .map(|rel| globs.is_match(rel))
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Turn a char-indexed replacement into a `TextEdit`; `line` is the text of
+/// the replacement's line, needed to express the columns in the negotiated
+/// position encoding.
+pub fn to_text_edit(line: &str, r: &Replacement, enc: crate::text::Encoding) -> TextEdit {
+    TextEdit {
+        range: Range {
+            start: Position {
+                line: r.line,
+                character: crate::text::col(line, r.start as usize, enc),
+            },
+            end: Position {
+                line: r.line,
+                character: crate::text::col(line, r.end as usize, enc),
+            },
+        },
+        new_text: r.symbol.clone(),
+    }
+}
+
+/// Build a `WorkspaceEdit` applying `replacements` to `uri`; `text` is the
+/// document the replacements were scanned from, for UTF-16 column
+/// conversion. With `annotate` each edit carries a `ChangeAnnotation` with
+/// `needsConfirmation` so clients can review and apply selectively.
+pub fn to_workspace_edit(
+    uri: Url,
+    text: &str,
+    replacements: &[Replacement],
+    annotate: bool,
+    enc: crate::text::Encoding,
+) -> WorkspaceEdit {
+    let line_of = |r: &Replacement| text.lines().nth(r.line as usize).unwrap_or("");
+    if annotate {
+        let mut annotations = HashMap::new();
+        let edits = replacements
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let id = format!("aim.{}", i);
+                annotations.insert(
+                    id.clone(),
+                    ChangeAnnotation {
+                        label: format!("\\{} → {}", r.sequence, r.symbol),
+                        needs_confirmation: Some(true),
+                        description: None,
+                    },
+                );
+                OneOf::Right(AnnotatedTextEdit {
+                    text_edit: to_text_edit(line_of(r), r, enc),
+                    annotation_id: id,
+                })
+            })
+            .collect();
+        WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                edits,
+            }])),
+            change_annotations: Some(annotations),
+            ..Default::default()
+        }
+    } else {
+        WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri,
+                replacements
+                    .iter()
+                    .map(|r| to_text_edit(line_of(r), r, enc))
+                    .collect(),
+            )])),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_scan() -> std::io::Result<()> {
+        let raw = std::fs::read("keymap.json")?;
+        let json: serde_json::Value =
+            serde_json::from_str(&String::from_utf8(raw).unwrap_or("".to_string()))?;
+        let keymap = Keymap::new(json);
+        let found = convert::scan(&keymap, "id : \\forall {A : Set} -> A -> A\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].sequence, "forall");
+        assert_eq!(found[0].symbol, "∀");
+        assert_eq!((found[0].start, found[0].end), (5, 12));
+        Ok(())
+    }
+
+    #[test]
+    fn test_crlf_preserved() {
+        let keymap = Keymap::from_flat_table(vec![("to".to_string(), vec!["→".to_string()])]);
+        let text = "a \\to b\r\nplain\r\nlast";
+        let found = convert::scan(&keymap, text);
+        assert_eq!(found.len(), 1);
+        assert_eq!(convert::apply(text, &found), "a → b\r\nplain\r\nlast");
+    }
+
+    #[test]
+    fn test_multichar_values() {
+        // replacement values are arbitrary strings: trailing spaces and
+        // line breaks insert as-is
+        let keymap = Keymap::from_flat_table(vec![
+            ("implies".to_string(), vec!["⟹ ".to_string()]),
+            ("qed".to_string(), vec!["∎\n".to_string()]),
+        ]);
+        let text = "p \\implies q \\qed";
+        let found = convert::scan(&keymap, text);
+        assert_eq!(found.len(), 2);
+        assert_eq!(convert::apply(text, &found), "p ⟹  q ∎\n");
+    }
+
+    #[test]
+    fn test_diff_multiline() {
+        let keymap =
+            Keymap::from_flat_table(vec![("qed".to_string(), vec!["∎\n".to_string()])]);
+        let text = "p \\qed\nunchanged\nlast";
+        let converted = convert::apply(text, &convert::scan(&keymap, text));
+        assert_eq!(converted, "p ∎\n\nunchanged\nlast");
+        // the inserted line break shifts everything below; untouched
+        // lines still never show up as changed
+        assert_eq!(
+            convert::diff(text, &converted),
+            "@@ -1,1 +1,2 @@\n-p \\qed\n+p ∎\n+\n"
+        );
+    }
+
+    #[test]
+    fn test_utf16_edit_columns() {
+        let keymap = Keymap::from_flat_table(vec![("to".to_string(), vec!["→".to_string()])]);
+        // the astral 𝔸 occupies one char but two utf-16 units
+        let text = "𝔸 \\to x\n";
+        let found = convert::scan(&keymap, text);
+        assert_eq!((found[0].start, found[0].end), (2, 5));
+        let line = text.lines().next().unwrap();
+        let edit = convert::to_text_edit(line, &found[0], text::Encoding::Utf16);
+        assert_eq!(edit.range.start.character, 3);
+        assert_eq!(edit.range.end.character, 6);
+        // a utf-8 client sees the same edit at byte columns
+        let edit = convert::to_text_edit(line, &found[0], text::Encoding::Utf8);
+        assert_eq!(edit.range.start.character, 5);
+    }
+}
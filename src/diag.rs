@@ -0,0 +1,223 @@
+//! Lints over open documents: escape sequences that could still be expanded,
+//! fullwidth lookalike punctuation easily left behind by an IME, invisible
+//! bidirectional controls and denylisted strings. Every rule's level is
+//! configurable per workspace.
+
+use crate::Keymap;
+use crate::config;
+use crate::convert;
+use crate::fuzzy;
+use tower_lsp::lsp_types::*;
+
+/// Fullwidth punctuation with the ASCII character it resembles.
+const CONFUSABLES: &[(char, char)] = &[
+    ('；', ';'),
+    ('：', ':'),
+    ('，', ','),
+    ('。', '.'),
+    ('（', '('),
+    ('）', ')'),
+    ('｛', '{'),
+    ('｝', '}'),
+    ('＂', '"'),
+    ('＇', '\''),
+    ('－', '-'),
+    ('　', ' '),
+];
+
+/// Invisible direction-override characters (CVE-2021-42574 territory).
+const BIDI_CONTROLS: &[char] = &[
+    '\u{061C}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}',
+    '\u{202E}', '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+fn severity(level: &str) -> Option<DiagnosticSeverity> {
+    match level {
+        "error" => Some(DiagnosticSeverity::ERROR),
+        "warning" => Some(DiagnosticSeverity::WARNING),
+        "info" | "information" => Some(DiagnosticSeverity::INFORMATION),
+        "hint" => Some(DiagnosticSeverity::HINT),
+        _ => None,
+    }
+}
+
+fn lint(line: u32, start: u32, end: u32, sev: DiagnosticSeverity, code: &str, message: String) -> Diagnostic {
+    Diagnostic {
+        range: Range {
+            start: Position::new(line, start),
+            end: Position::new(line, end),
+        },
+        severity: Some(sev),
+        code: Some(NumberOrString::String(code.to_string())),
+        source: Some("aim".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// The known sequence closest to `token` within two edits, if any; ties go
+/// to the lexicographically first.
+fn nearest(keymap: &Keymap, token: &str) -> Option<String> {
+    keymap
+        .entries()
+        .into_iter()
+        .map(|(seq, _)| seq)
+        .filter(|seq| seq.chars().count().abs_diff(token.chars().count()) <= 2)
+        .map(|seq| (fuzzy::edit_distance(token, &seq), seq))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, seq)| seq)
+}
+
+/// Compute the full lint set for one document, honoring the per-rule levels.
+pub fn collect(keymap: &Keymap, text: &str, settings: &config::Settings) -> Vec<Diagnostic> {
+    let rules = &settings.diagnostic_rules;
+    let trigger = settings.trigger.chars().next().unwrap_or('\\');
+    let mut diags = vec![];
+    if let Some(sev) = severity(&rules.unexpanded_sequence) {
+        for r in convert::scan_with(keymap, text, trigger) {
+            diags.push(lint(
+                r.line,
+                r.start,
+                r.end,
+                sev,
+                "unexpanded-sequence",
+                format!("`\\{}` expands to `{}`", r.sequence, r.symbol),
+            ));
+        }
+    }
+    if let Some(sev) = severity(&rules.unknown_sequence) {
+        for (ln, line) in text.lines().enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                if chars[i] == trigger {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|c| c.is_whitespace() || *c == trigger)
+                        .map(|n| i + 1 + n)
+                        .unwrap_or(chars.len());
+                    let token: String = chars[i + 1..end].iter().collect();
+                    // a valid prefix is merely pending, not unknown
+                    if !token.is_empty()
+                        && keymap.lookup(&token).is_empty()
+                        && keymap.longest_match(&token).is_none()
+                    {
+                        let mut message = format!("unknown input sequence `\\{}`", token);
+                        if let Some(near) = nearest(keymap, &token) {
+                            message.push_str(&format!("; did you mean `\\{}`?", near));
+                        }
+                        diags.push(lint(
+                            ln as u32,
+                            i as u32,
+                            end as u32,
+                            sev,
+                            "unknown-sequence",
+                            message,
+                        ));
+                    }
+                    i = end.max(i + 1);
+                    continue;
+                }
+                i += 1;
+            }
+        }
+    }
+    for (ln, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        for (col, &c) in chars.iter().enumerate() {
+            if let Some(sev) = severity(&rules.confusable)
+                && let Some((_, ascii)) = CONFUSABLES.iter().find(|(f, _)| *f == c)
+            {
+                diags.push(lint(
+                    ln as u32,
+                    col as u32,
+                    col as u32 + 1,
+                    sev,
+                    "confusable",
+                    format!("`{}` looks like ASCII `{}`", c, ascii),
+                ));
+            }
+            if let Some(sev) = severity(&rules.bidi_control)
+                && BIDI_CONTROLS.contains(&c)
+            {
+                diags.push(lint(
+                    ln as u32,
+                    col as u32,
+                    col as u32 + 1,
+                    sev,
+                    "bidi-control",
+                    format!("bidirectional control character U+{:04X}", c as u32),
+                ));
+            }
+        }
+        if let Some(sev) = severity(&rules.denylist) {
+            for entry in &settings.denylist {
+                let pat: Vec<char> = entry.chars().collect();
+                if pat.is_empty() || pat.len() > chars.len() {
+                    continue;
+                }
+                for col in 0..=chars.len() - pat.len() {
+                    if chars[col..col + pat.len()] == pat[..] {
+                        diags.push(lint(
+                            ln as u32,
+                            col as u32,
+                            (col + pat.len()) as u32,
+                            sev,
+                            "denylist",
+                            format!("`{}` is denylisted in this workspace", entry),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    diags
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect() -> std::io::Result<()> {
+        let raw = std::fs::read("keymap.json")?;
+        let keymap = Keymap::new(serde_json::from_slice(&raw)?);
+        let settings = config::Settings::default();
+        let diags = collect(&keymap, "id : \\forall A； A\u{202E}\n", &settings);
+        assert_eq!(diags.len(), 3);
+        assert!(diags[0].message.contains("∀"));
+        assert!(diags[1].message.contains(";"));
+        assert!(diags[2].message.contains("U+202E"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_sequence() {
+        let keymap =
+            Keymap::from_flat_table(vec![("lambda".to_string(), vec!["λ".to_string()])]);
+        let settings = config::Settings::default();
+        let diags = collect(&keymap, "\\lamda x\n", &settings);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(
+            diags[0].code,
+            Some(NumberOrString::String("unknown-sequence".to_string()))
+        );
+        assert!(diags[0].message.contains("did you mean `\\lambda`"));
+    }
+
+    #[test]
+    fn test_rule_levels() {
+        let keymap = Keymap::new(serde_json::Value::Null);
+        let mut settings = config::Settings::default();
+        settings.diagnostic_rules.confusable = "off".to_string();
+        settings.denylist = vec!["ƛ".to_string()];
+        let diags = collect(&keymap, "ƛ；\n", &settings);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diags[0].code,
+            Some(NumberOrString::String("denylist".to_string()))
+        );
+    }
+}
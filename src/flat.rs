@@ -0,0 +1,121 @@
+//! A flat, cache-friendly rendering of the keymap trie.
+//!
+//! `Keymap` keeps one heap-allocated `BTreeMap` per node, which is the right
+//! shape for building and merging layers but chases a pointer per typed
+//! character. `FlatTrie` lays the same data out as a single node arena:
+//! children are `(edge char, node index)` pairs found by binary search, so a
+//! descent touches one contiguous `Vec` instead of a box per character.
+//! Built once per keymap rebuild, read on every keystroke.
+
+use crate::Keymap;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct FlatTrie {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    /// Sorted `(edge char, child index)` pairs; `BTreeMap` iteration hands
+    /// them over already ordered.
+    children: Vec<(char, u32)>,
+    here: Vec<Arc<str>>,
+    hidden: Vec<Arc<str>>,
+}
+
+impl FlatTrie {
+    /// Lay the nested trie out into the arena, forcing lazy namespaces.
+    /// Gated symbols are document-dependent and stay with the nested trie;
+    /// callers layer them back in via `Keymap::gated_at`.
+    pub fn build(keymap: &Keymap) -> Self {
+        fn walk(keymap: &Keymap, at: usize, nodes: &mut Vec<Node>) {
+            let keymap = keymap.resolve();
+            nodes[at].here = keymap.here.clone();
+            nodes[at].hidden = keymap.hidden.clone();
+            for (c, child) in &keymap.cont {
+                let idx = nodes.len() as u32;
+                nodes.push(Node::default());
+                nodes[at].children.push((*c, idx));
+                walk(child, idx as usize, nodes);
+            }
+        }
+        let mut nodes = vec![Node::default()];
+        walk(keymap, 0, &mut nodes);
+        FlatTrie { nodes }
+    }
+
+    /// Same answer and order as `Keymap::lookup`: hidden symbols only on an
+    /// exact match, continuations flattened breadth-first in key order.
+    pub fn lookup(&self, prefix: &str) -> Vec<Arc<str>> {
+        let mut at = 0usize;
+        for c in prefix.chars() {
+            let children = &self.nodes[at].children;
+            match children.binary_search_by_key(&c, |(edge, _)| *edge) {
+                Ok(i) => at = children[i].1 as usize,
+                Err(_) => return vec![],
+            }
+        }
+        let mut ret = self.nodes[at].here.clone();
+        ret.extend(self.nodes[at].hidden.iter().cloned());
+        let mut level: Vec<usize> = self.nodes[at]
+            .children
+            .iter()
+            .map(|(_, i)| *i as usize)
+            .collect();
+        while !level.is_empty() {
+            let mut next = vec![];
+            for i in level {
+                ret.extend(self.nodes[i].here.iter().cloned());
+                next.extend(self.nodes[i].children.iter().map(|(_, j)| *j as usize));
+            }
+            level = next;
+        }
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // the root node always exists; empty means no entries below it
+        self.nodes.len() == 1 && self.nodes[0].here.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches_nested_lookup() {
+        let keymap = Keymap::embedded();
+        let flat = FlatTrie::build(&keymap);
+        for prefix in ["", "G", "Gl-", "to", "forall", "nope"] {
+            assert_eq!(flat.lookup(prefix), keymap.lookup(prefix), "{}", prefix);
+        }
+    }
+
+    #[test]
+    #[ignore = "timing comparison, run with --ignored --nocapture"]
+    fn bench_flat_vs_nested() {
+        let table: Vec<(String, Vec<String>)> = (0..10_000)
+            .map(|i| (format!("seq{:05}", i), vec![format!("s{}", i)]))
+            .collect();
+        let keymap = Keymap::from_flat_table(table);
+        let flat = FlatTrie::build(&keymap);
+        let time = |f: &dyn Fn() -> usize| {
+            let start = std::time::Instant::now();
+            let mut total = 0;
+            for _ in 0..1_000 {
+                total += f();
+            }
+            (start.elapsed(), total)
+        };
+        let (nested, a) = time(&|| keymap.lookup("seq1").len());
+        let (arena, b) = time(&|| flat.lookup("seq1").len());
+        assert_eq!(a, b);
+        println!("nested: {:?}, flat: {:?}", nested, arena);
+    }
+}
@@ -0,0 +1,178 @@
+//! Keyboard-adjacency typo tolerance.
+//!
+//! When a prefix matches nothing, the common cause is hitting a key next to
+//! the intended one while typing a sequence fast. This models the keyboard
+//! as rows of keys (QWERTY by default, other layouts configurable) and
+//! generates single-substitution variants using physically adjacent keys.
+
+use std::collections::HashMap;
+
+pub const QWERTY: &[&str] = &["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+#[derive(Debug)]
+pub struct Adjacency {
+    neighbors: HashMap<char, Vec<char>>,
+}
+
+impl Adjacency {
+    pub fn new(rows: &[&str]) -> Self {
+        let rows: Vec<Vec<char>> = rows.iter().map(|r| r.chars().collect()).collect();
+        let mut neighbors: HashMap<char, Vec<char>> = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                let mut adj = vec![];
+                for dy in -1i32..=1 {
+                    for dx in -1i32..=1 {
+                        if (dx, dy) == (0, 0) {
+                            continue;
+                        }
+                        if let Some(&n) = rows
+                            .get((y as i32 + dy) as usize)
+                            .and_then(|r| r.get((x as i32 + dx) as usize))
+                        {
+                            adj.push(n);
+                        }
+                    }
+                }
+                neighbors.insert(c, adj);
+            }
+        }
+        Adjacency { neighbors }
+    }
+
+    /// Every string one adjacent-key substitution away from `s`.
+    pub fn variants(&self, s: &str) -> Vec<String> {
+        let chars: Vec<char> = s.chars().collect();
+        let mut ret = vec![];
+        for (i, c) in chars.iter().enumerate() {
+            for n in self
+                .neighbors
+                .get(&c.to_ascii_lowercase())
+                .into_iter()
+                .flatten()
+            {
+                let mut v = chars.clone();
+                v[i] = if c.is_ascii_uppercase() {
+                    n.to_ascii_uppercase()
+                } else {
+                    *n
+                };
+                ret.push(v.iter().collect());
+            }
+        }
+        ret
+    }
+}
+
+/// Levenshtein distance between two sequences, for did-you-mean suggestions.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let subst = prev[j] + usize::from(ca != cb);
+            row.push(subst.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// Secondary index over the flattened keymap for the optional fuzzy mode:
+/// sequences are matched by substring and by subsequence, and symbols by
+/// their Unicode character names, so `\ra` finds `rightarrow`-style entries
+/// and `\lam` finds λ even through an unfamiliar sequence.
+#[derive(Debug)]
+pub struct FuzzyIndex {
+    /// (lowercased sequence, lowercased character names, symbol)
+    entries: Vec<(String, String, String)>,
+}
+
+impl FuzzyIndex {
+    pub fn new(table: &[(String, String)]) -> Self {
+        let mut entries: Vec<_> = table
+            .iter()
+            .map(|(seq, sym)| {
+                (
+                    seq.to_lowercase(),
+                    crate::unicode::names(sym).to_lowercase(),
+                    sym.clone(),
+                )
+            })
+            .collect();
+        entries.sort();
+        entries.dedup();
+        FuzzyIndex { entries }
+    }
+
+    /// Candidates for `query`, best class first: substring matches on the
+    /// sequence, then subsequence matches, then name matches.
+    pub fn lookup(&self, query: &str) -> Vec<String> {
+        fn push(ret: &mut Vec<String>, sym: &str) {
+            if !ret.iter().any(|s| s == sym) {
+                ret.push(sym.to_string());
+            }
+        }
+        fn subsequence(query: &str, seq: &str) -> bool {
+            let mut wanted = query.chars().peekable();
+            for c in seq.chars() {
+                if wanted.peek() == Some(&c) {
+                    wanted.next();
+                }
+            }
+            wanted.peek().is_none()
+        }
+        let q = query.to_lowercase();
+        let mut ret = vec![];
+        for (seq, _, sym) in &self.entries {
+            if seq.contains(&q) {
+                push(&mut ret, sym);
+            }
+        }
+        for (seq, _, sym) in &self.entries {
+            if subsequence(&q, seq) {
+                push(&mut ret, sym);
+            }
+        }
+        for (_, names, sym) in &self.entries {
+            if names.contains(&q) {
+                push(&mut ret, sym);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_adjacent_key_variants() {
+        let adjacency = Adjacency::new(QWERTY);
+        assert!(adjacency.variants("kambda").contains(&"lambda".to_string()));
+        assert!(!adjacency.variants("to").contains(&"to".to_string()));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("lamda", "lambda"), 1);
+        assert_eq!(edit_distance("to", "to"), 0);
+        assert_eq!(edit_distance("", "ab"), 2);
+    }
+
+    #[test]
+    fn test_fuzzy_index() {
+        let index = FuzzyIndex::new(&[
+            ("rightarrow".to_string(), "→".to_string()),
+            ("Gl-".to_string(), "ƛ".to_string()),
+        ]);
+        // substring and subsequence of the sequence
+        assert_eq!(index.lookup("arrow"), vec!["→"]);
+        assert_eq!(index.lookup("ra"), vec!["→"]);
+        // match on the symbol's Unicode name (ƛ is "lambda with stroke")
+        assert_eq!(index.lookup("lam"), vec!["ƛ"]);
+    }
+}
@@ -0,0 +1,61 @@
+//! Beta-code style Greek transliteration: one rule per Latin letter plus
+//! the common digraphs, composed over the trie instead of one keymap entry
+//! per letter/case combination.
+
+/// Latin → Greek rules. Digraphs come first so the scanner can try the
+/// longest rule at each position; the single letters follow the usual
+/// beta-code assignments (`q` → θ, `c` → ξ, `y` → ψ, `w` → ω).
+const RULES: &[(&str, &str)] = &[
+    ("th", "θ"), ("ph", "φ"), ("ch", "χ"), ("ps", "ψ"),
+    ("Th", "Θ"), ("Ph", "Φ"), ("Ch", "Χ"), ("Ps", "Ψ"),
+    ("a", "α"), ("b", "β"), ("g", "γ"), ("d", "δ"), ("e", "ε"),
+    ("z", "ζ"), ("h", "η"), ("q", "θ"), ("i", "ι"), ("k", "κ"),
+    ("l", "λ"), ("m", "μ"), ("n", "ν"), ("c", "ξ"), ("o", "ο"),
+    ("p", "π"), ("r", "ρ"), ("s", "σ"), ("t", "τ"), ("u", "υ"),
+    ("f", "φ"), ("x", "χ"), ("y", "ψ"), ("w", "ω"),
+    ("A", "Α"), ("B", "Β"), ("G", "Γ"), ("D", "Δ"), ("E", "Ε"),
+    ("Z", "Ζ"), ("H", "Η"), ("Q", "Θ"), ("I", "Ι"), ("K", "Κ"),
+    ("L", "Λ"), ("M", "Μ"), ("N", "Ν"), ("C", "Ξ"), ("O", "Ο"),
+    ("P", "Π"), ("R", "Ρ"), ("S", "Σ"), ("T", "Τ"), ("U", "Υ"),
+    ("F", "Φ"), ("X", "Χ"), ("Y", "Ψ"), ("W", "Ω"),
+];
+
+/// Transliterate `input` rule by rule, longest rule first. `None` when any
+/// part has no rule, so unrelated sequences fall through to the trie.
+pub fn transliterate(input: &str) -> Option<String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let two: String = chars[i..].iter().take(2).collect();
+        if let Some((_, greek)) = RULES.iter().find(|(latin, _)| *latin == two) {
+            out.push_str(greek);
+            i += 2;
+            continue;
+        }
+        let one = chars[i].to_string();
+        let (_, greek) = RULES.iter().find(|(latin, _)| *latin == one)?;
+        out.push_str(greek);
+        i += 1;
+    }
+    (!out.is_empty()).then_some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(transliterate("a"), Some("α".to_string()));
+        assert_eq!(transliterate("G"), Some("Γ".to_string()));
+        // digraphs win over their first letter
+        assert_eq!(transliterate("th"), Some("θ".to_string()));
+        assert_eq!(transliterate("Ps"), Some("Ψ".to_string()));
+        // whole words transliterate letter by letter
+        assert_eq!(transliterate("logos"), Some("λογοσ".to_string()));
+        // anything outside the table falls through to the trie
+        assert_eq!(transliterate("l-"), None);
+        assert_eq!(transliterate(""), None);
+    }
+}
@@ -0,0 +1,386 @@
+//! Importers for foreign keymap formats. Each loader lowers its source into
+//! flat `(sequence, symbols)` pairs; `Keymap::from_file` folds those into the
+//! trie, so users can point the server at tables they already maintain.
+
+/// Parse the Quail translation table out of Emacs' `agda-input.el`, the
+/// canonical source for `\to`, `\Gl` and friends.
+///
+/// Entries look like `("to" . ,(agda-input-to-string-list "→⟶"))`,
+/// `("ell" . ("ℓ"))` or `("Gl" . "ƛ")`; everything else in the file
+/// (elisp code, comments) is ignored.
+pub fn parse_quail(text: &str) -> Vec<(String, Vec<String>)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        if !(chars[i] == '(' && chars.get(i + 1) == Some(&'"')) {
+            i += 1;
+            continue;
+        }
+        let mut depth = 0usize;
+        let mut strings: Vec<String> = vec![];
+        let mut expand = false;
+        let mut word = String::new();
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        i += 1;
+                        break;
+                    }
+                }
+                '"' => {
+                    let mut s = String::new();
+                    i += 1;
+                    while let Some(&c) = chars.get(i) {
+                        match c {
+                            '\\' => {
+                                if let Some(&escaped) = chars.get(i + 1) {
+                                    s.push(escaped);
+                                    i += 1;
+                                }
+                            }
+                            '"' => break,
+                            _ => s.push(c),
+                        }
+                        i += 1;
+                    }
+                    strings.push(s);
+                }
+                // elisp comment runs to the end of the line
+                ';' => while chars.get(i + 1).is_some_and(|&c| c != '\n') {
+                    i += 1;
+                },
+                _ => {}
+            }
+            if c.is_alphanumeric() || c == '-' {
+                word.push(c);
+            } else {
+                // `,(agda-input-to-string-list "…")` means one symbol per char
+                expand |= word == "agda-input-to-string-list";
+                word.clear();
+            }
+            i += 1;
+        }
+        if let Some((key, symbols)) = strings.split_first()
+            && !symbols.is_empty()
+        {
+            let symbols = if expand && symbols.len() == 1 {
+                symbols[0].chars().map(String::from).collect()
+            } else {
+                symbols.to_vec()
+            };
+            out.push((key.clone(), symbols));
+        }
+    }
+    out
+}
+
+/// Parse Julia REPL `latex_symbols.jl`-style flat tables, one
+/// `"\\alpha" => "α",` pair per line. The leading backslash of the sequence
+/// is dropped since the trigger character supplies it.
+pub fn parse_flat_table(text: &str) -> Vec<(String, Vec<String>)> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once("=>")?;
+            let key = unquote(key.trim())?;
+            let value = unquote(value.trim().trim_end_matches(','))?;
+            let key = key.strip_prefix('\\').unwrap_or(&key).to_string();
+            (!key.is_empty() && !value.is_empty()).then_some((key, vec![value]))
+        })
+        .collect()
+}
+
+/// Parse Vim `:digraphs` output captured with `:redir`: `a! ∠  8736`
+/// triples, several per line. Each digraph lands under `prefix` in the trie,
+/// so with the default prefix `d`, typing `\da!` inserts `∠`. The decimal
+/// codepoint is authoritative; the printed glyph column is only resynced on.
+pub fn parse_digraphs(text: &str, prefix: &str) -> Vec<(String, Vec<String>)> {
+    let mut out = vec![];
+    for line in text.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let mut i = 0;
+        while i + 3 <= tokens.len() {
+            if tokens[i].chars().count() == 2
+                && let Ok(dec) = tokens[i + 2].parse::<u32>()
+                && let Some(c) = char::from_u32(dec)
+            {
+                out.push((format!("{}{}", prefix, tokens[i]), vec![c.to_string()]));
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Parse a flat TOML keymap: `"Gl-" = ["ƛ"]` or `to = "→"`, one sequence per
+/// key. Much friendlier to author by hand than the nested JSON trie with its
+/// magic `">>"` key.
+pub fn parse_toml(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| e.to_string())?;
+    let mut out = vec![];
+    for (key, value) in table {
+        let symbols = match value {
+            toml::Value::String(s) => vec![s],
+            toml::Value::Array(a) => a
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect(),
+            other => {
+                return Err(format!("`{}` maps to {}, not a symbol or list", key, other));
+            }
+        };
+        out.push((key, symbols));
+    }
+    Ok(out)
+}
+
+/// Parse a flat JSON keymap (`{"to": ["→"], "alpha": "α"}`), the shape the
+/// `convert` subcommand upgrades to the nested trie format.
+pub fn parse_flat_json(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(&strip_jsonc(text)).map_err(|e| e.to_string())?;
+    let Some(obj) = json.as_object() else {
+        return Err("expected a top-level object".to_string());
+    };
+    let mut out = vec![];
+    for (key, value) in obj {
+        let symbols = match value {
+            serde_json::Value::String(s) => vec![s.clone()],
+            serde_json::Value::Array(a) => a
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect(),
+            other => {
+                return Err(format!("`{}` maps to {}, not a symbol or list", key, other));
+            }
+        };
+        out.push((key.clone(), symbols));
+    }
+    Ok(out)
+}
+
+/// A declarative sequence family: one rule stamped out over a range of
+/// characters, so `\_0`…`\_9` need not be ten keymap entries. `pattern`
+/// contains `{}` standing for each source character in turn; `from` and
+/// `to` pair up character by character, which also handles ranges with
+/// holes (the superscript digits are scattered across two blocks, the
+/// italic `h` is ℎ outside the math alphabet).
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Family {
+    pub pattern: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Expand family rules into the flat pairs the other importers produce:
+/// `{ pattern = "_{}", from = "0123456789", to = "₀₁₂₃₄₅₆₇₈₉" }` yields
+/// all ten subscript entries.
+pub fn expand_families(families: &[Family]) -> Vec<(String, Vec<String>)> {
+    let mut table = vec![];
+    for family in families {
+        for (src, dst) in family.from.chars().zip(family.to.chars()) {
+            table.push((
+                family.pattern.replace("{}", &src.to_string()),
+                vec![dst.to_string()],
+            ));
+        }
+    }
+    table
+}
+
+/// Strip `//` and `/* */` comments plus trailing commas, so hand-maintained
+/// keymaps can be JSONC. String contents are left untouched; the result is
+/// plain JSON for `serde_json`.
+pub fn strip_jsonc(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out: Vec<char> = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    let mut in_string = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => {
+                    if let Some(&escaped) = chars.get(i + 1) {
+                        out.push(escaped);
+                        i += 1;
+                    }
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => {
+                    in_string = true;
+                    out.push(c);
+                }
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while chars.get(i + 1).is_some_and(|&c| c != '\n') {
+                        i += 1;
+                    }
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    i += 2;
+                    while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                _ => out.push(c),
+            }
+        }
+        i += 1;
+    }
+    // second pass: drop commas that now directly precede a closing bracket
+    let mut ret = String::with_capacity(out.len());
+    let mut in_string = false;
+    let mut escape = false;
+    for (i, &c) in out.iter().enumerate() {
+        if in_string {
+            ret.push(c);
+            if escape {
+                escape = false;
+            } else if c == '\\' {
+                escape = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                ret.push(c);
+            }
+            ',' => {
+                let next = out[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}') | Some(']')) {
+                    ret.push(c);
+                }
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn unquote(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    // the only escapes these tables actually use
+    Some(inner.replace("\\\\", "\\").replace("\\\"", "\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_quail() {
+        let el = r#"
+;; A fragment in the shape of agda-input.el:
+(agda-input-add-translations
+ `(("to" . ,(agda-input-to-string-list "→⟶"))
+   ("ell" . ("ℓ"))
+   ("Gl" . "ƛ")))
+"#;
+        let table = parse_quail(el);
+        assert_eq!(
+            table,
+            vec![
+                ("to".to_string(), vec!["→".to_string(), "⟶".to_string()]),
+                ("ell".to_string(), vec!["ℓ".to_string()]),
+                ("Gl".to_string(), vec!["ƛ".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_flat_table() {
+        let jl = r#"
+const latex_symbols = Dict(
+    "\\alpha" => "α",
+    "\\to" => "→",
+)
+"#;
+        let table = parse_flat_table(jl);
+        assert_eq!(
+            table,
+            vec![
+                ("alpha".to_string(), vec!["α".to_string()]),
+                ("to".to_string(), vec!["→".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_digraphs() {
+        let vim = "a! ∠  8736  a( ∟  8735\nOK ✓  10003";
+        let table = parse_digraphs(vim, "d");
+        assert_eq!(
+            table,
+            vec![
+                ("da!".to_string(), vec!["∠".to_string()]),
+                ("da(".to_string(), vec!["∟".to_string()]),
+                ("dOK".to_string(), vec!["✓".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let toml = r#"
+# personal additions
+"Gl-" = ["ƛ"]
+to = "→"
+"#;
+        let table = parse_toml(toml).unwrap();
+        assert!(table.contains(&("Gl-".to_string(), vec!["ƛ".to_string()])));
+        assert!(table.contains(&("to".to_string(), vec!["→".to_string()])));
+        assert!(parse_toml("bad = 3").is_err());
+    }
+
+    #[test]
+    fn test_expand_families() {
+        let families = vec![
+            Family {
+                pattern: "_{}".to_string(),
+                from: "012".to_string(),
+                to: "₀₁₂".to_string(),
+            },
+            Family {
+                pattern: "^{}".to_string(),
+                from: "012".to_string(),
+                to: "⁰¹²".to_string(),
+            },
+        ];
+        let table = expand_families(&families);
+        assert!(table.contains(&("_1".to_string(), vec!["₁".to_string()])));
+        assert!(table.contains(&("^2".to_string(), vec!["²".to_string()])));
+        assert_eq!(table.len(), 6);
+    }
+
+    #[test]
+    fn test_strip_jsonc() {
+        let jsonc = r#"
+{
+  // arrows
+  "t": { "o": { ">>": ["→"], }, }, /* trailing
+  commas everywhere */
+  "u": { ">>": ["http://…", "a,b"] },
+}
+"#;
+        let json: serde_json::Value = serde_json::from_str(&strip_jsonc(jsonc)).unwrap();
+        assert_eq!(json["t"]["o"][">>"][0], "→");
+        // comment markers and commas inside strings survive
+        assert_eq!(json["u"][">>"][0], "http://…");
+        assert_eq!(json["u"][">>"][1], "a,b");
+    }
+}
@@ -0,0 +1,1025 @@
+//! Trie-based lookup engine for Agda-style `\sequence` Unicode input.
+//!
+//! The `aim-lsp` binary wraps this in an LSP server; the library exposes the
+//! keymap itself — building ([`Keymap::from_file`], the importers in
+//! [`keymap`]), merging ([`Keymap::merge`]) and prefix lookups
+//! ([`Keymap::lookup`] and friends) — so non-LSP tools can reuse the engine.
+
+pub mod cache;
+pub mod cjk;
+pub mod config;
+pub mod context;
+pub mod convert;
+pub mod diag;
+pub mod flat;
+pub mod fuzzy;
+pub mod greek;
+pub mod keymap;
+pub mod notebook;
+pub mod requests;
+pub mod reverse;
+pub mod stats;
+pub mod text;
+pub mod unicode;
+pub mod xref;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::{Arc, OnceLock};
+
+
+/// Hard limits on keymap files: a corrupted or malicious keymap in a cloned
+/// repo should fail loudly instead of hanging or exhausting memory.
+const MAX_KEYMAP_BYTES: u64 = 16 * 1024 * 1024;
+const MAX_KEYMAP_DEPTH: usize = 32;
+const MAX_KEY_CHARS: usize = 64;
+const MAX_KEYMAP_ENTRIES: usize = 1 << 20;
+
+/// Default keymap compiled into the binary, so the server works out of the
+/// box without a `keymap.json` next to it; external keymaps merge over it.
+const EMBEDDED_KEYMAP: &str = include_str!("../keymap.json");
+
+/// GitHub-style emoji shortcodes bundled for the `:shortcode:` mode; the
+/// common ones people reach for in commit messages and docs.
+const EMBEDDED_EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("boom", "💥"),
+    ("bug", "🐛"),
+    ("bulb", "💡"),
+    ("cat", "🐱"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("confused", "😕"),
+    ("crab", "🦀"),
+    ("cry", "😢"),
+    ("dog", "🐶"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("gear", "⚙"),
+    ("grin", "😁"),
+    ("heart", "❤"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("link", "🔗"),
+    ("lock", "🔒"),
+    ("mag", "🔍"),
+    ("memo", "📝"),
+    ("package", "📦"),
+    ("penguin", "🐧"),
+    ("pray", "🙏"),
+    ("question", "❓"),
+    ("rainbow", "🌈"),
+    ("rocket", "🚀"),
+    ("scream", "😱"),
+    ("smile", "😄"),
+    ("snake", "🐍"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsup", "👍"),
+    ("turtle", "🐢"),
+    ("unlock", "🔓"),
+    ("warning", "⚠"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("wrench", "🔧"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+    ("zzz", "💤"),
+];
+
+#[derive(Debug)]
+pub struct Keymap {
+    here: Vec<Arc<str>>,
+    /// Symbols at this node that are only offered in documents matching
+    /// their glob filters (`{"symbol": "∎", "files": ["**/*.agda"]}`).
+    gated: Vec<GatedSymbol>,
+    /// Symbols that only complete on an exact prefix match
+    /// (`{"symbol": "…", "hidden": true}`); flattening skips them so long,
+    /// rarely needed sequences don't clutter every short-prefix list.
+    hidden: Vec<Arc<str>>,
+    /// Alternate output spellings declared on entries at this node, as
+    /// (flavor, symbol, spelling): `{"symbol": "→", "latex": "\\rightarrow"}`
+    /// records `("latex", "→", "\\rightarrow")`. Lookup still deals in the
+    /// symbol; the flavor table substitutes the spelling at insertion time.
+    flavors: Vec<(Arc<str>, Arc<str>, Arc<str>)>,
+    cont: BTreeMap<char, Keymap>,
+    /// Subtree loaded on demand from a split keymap file.
+    lazy: Option<Arc<LazyNamespace>>,
+    /// Memoized exact-match candidate list for this node (own symbols plus
+    /// the flattened subtree), filled on first lookup and dropped by the
+    /// mutating methods; repeated keystrokes stop re-cloning every
+    /// descendant's symbols.
+    flat: OnceLock<Vec<Arc<str>>>,
+}
+
+impl Clone for Keymap {
+    fn clone(&self) -> Self {
+        Keymap {
+            here: self.here.clone(),
+            gated: self.gated.clone(),
+            hidden: self.hidden.clone(),
+            flavors: self.flavors.clone(),
+            cont: self.cont.clone(),
+            lazy: self.lazy.clone(),
+            // clones are usually made to be merged into; the cache is
+            // cheap to rebuild on demand
+            flat: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GatedSymbol {
+    symbol: Arc<str>,
+    globs: globset::GlobSet,
+}
+
+/// One candidate from a ranked lookup: the full sequence that produces the
+/// symbol and how many characters of it remain past the queried prefix.
+/// Where a flat symbol list loses the structure, this keeps enough of it
+/// for ranking — exact matches are depth 0, and `\l` can show `λ` ahead of
+/// obscure long sequences no matter which source produced the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub sequence: String,
+    pub symbol: Arc<str>,
+    pub depth: usize,
+}
+
+/// A namespace whose entries live in their own file (`"emoji": "emoji.json"`
+/// in the index), parsed the first time the prefix is actually looked up.
+#[derive(Debug)]
+struct LazyNamespace {
+    path: PathBuf,
+    loaded: OnceLock<Keymap>,
+}
+
+impl LazyNamespace {
+    fn force(&self) -> &Keymap {
+        self.loaded.get_or_init(|| {
+            std::fs::read_to_string(&self.path)
+                .ok()
+                .and_then(|raw| serde_json::from_str(&keymap::strip_jsonc(&raw)).ok())
+                .and_then(|json| Keymap::load(&json, self.path.parent().unwrap_or(Path::new("."))))
+                .unwrap_or_else(Keymap::empty)
+        })
+    }
+}
+
+impl Keymap {
+    pub fn new(json: serde_json::Value) -> Self {
+        let mut keymap = Self::embedded();
+        keymap.merge(Self::with_base(json, Path::new(".")));
+        keymap
+    }
+
+    /// The compiled-in default keymap, parsed once per process.
+    pub fn embedded() -> Self {
+        static EMBEDDED: OnceLock<Keymap> = OnceLock::new();
+        EMBEDDED
+            .get_or_init(|| {
+                let json = serde_json::from_str(EMBEDDED_KEYMAP).expect("embedded keymap parses");
+                Self::load(&json, Path::new(".")).unwrap_or_else(Keymap::empty)
+            })
+            .clone()
+    }
+
+    /// Build a keymap, resolving split-file references relative to `base`.
+    pub fn with_base(json: serde_json::Value, base: &Path) -> Self {
+        Self::load(&json, base).unwrap_or_else(Keymap::empty)
+    }
+
+    /// Check a raw keymap document against the hard limits before building
+    /// the trie from it.
+    pub fn validate(
+        json: &serde_json::Value,
+        depth: usize,
+        entries: &mut usize,
+    ) -> std::result::Result<(), String> {
+        if depth > MAX_KEYMAP_DEPTH {
+            return Err(format!(
+                "keymap nests deeper than {} levels",
+                MAX_KEYMAP_DEPTH
+            ));
+        }
+        if let Some(obj) = json.as_object() {
+            for (key, value) in obj {
+                if key.chars().count() > MAX_KEY_CHARS {
+                    return Err(format!(
+                        "key `{}…` is longer than {} characters",
+                        key.chars().take(8).collect::<String>(),
+                        MAX_KEY_CHARS
+                    ));
+                }
+                *entries += 1;
+                if *entries > MAX_KEYMAP_ENTRIES {
+                    return Err(format!(
+                        "keymap has more than {} entries",
+                        MAX_KEYMAP_ENTRIES
+                    ));
+                }
+                Self::validate(value, depth + 1, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a keymap file with the hard limits enforced: size cap before
+    /// reading, then depth/key/entry validation before the build.
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+        if size > MAX_KEYMAP_BYTES {
+            return Err(format!(
+                "{} is {} bytes, over the {} byte limit",
+                path.display(),
+                size,
+                MAX_KEYMAP_BYTES
+            ));
+        }
+        // foreign formats load as-is, dispatched by extension
+        match path.extension().and_then(|e| e.to_str()) {
+            // Emacs agda-input.el Quail tables
+            Some("el") => {
+                let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+                return Ok(Self::from_flat_table(keymap::parse_quail(&text)));
+            }
+            // Julia REPL latex_symbols.jl flat tables
+            Some("jl") => {
+                let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+                return Ok(Self::from_flat_table(keymap::parse_flat_table(&text)));
+            }
+            // flat TOML keymaps, one sequence per key
+            Some("toml") => {
+                let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+                return Ok(Self::from_flat_table(keymap::parse_toml(&text)?));
+            }
+            // Vim :digraphs dumps, under the default `d` prefix
+            Some("digraphs") => {
+                let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+                return Ok(Self::from_flat_table(keymap::parse_digraphs(&text, "d")));
+            }
+            _ => {}
+        }
+        let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        // tolerate jsonc so hand-maintained keymaps can carry comments
+        let mut json: serde_json::Value =
+            serde_json::from_str(&keymap::strip_jsonc(&raw)).map_err(|e| e.to_string())?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let mut stack = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+        Self::expand_includes(&mut json, base, &mut stack)?;
+        let mut entries = 0;
+        Self::validate(&json, 0, &mut entries)?;
+        Ok(Self::with_base(json, base))
+    }
+
+    /// Splice every `"$include"` directive in place: the named files,
+    /// resolved relative to the including one, are parsed and merged under
+    /// the node carrying the directive, so large keymaps can be organized
+    /// into composable modules. Keys the including file already defines
+    /// win. `stack` holds the chain of files being expanded; re-entering
+    /// one is a cycle and an error rather than a hang.
+    fn expand_includes(
+        json: &mut serde_json::Value,
+        base: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> std::result::Result<(), String> {
+        let Some(obj) = json.as_object_mut() else {
+            return Ok(());
+        };
+        let includes = match obj.remove("$include") {
+            Some(serde_json::Value::Array(files)) => files,
+            Some(file @ serde_json::Value::String(_)) => vec![file],
+            Some(other) => return Err(format!("`$include` must name files, got {}", other)),
+            None => vec![],
+        };
+        for file in includes {
+            let Some(file) = file.as_str() else {
+                return Err(format!("`$include` must name files, got {}", file));
+            };
+            let path = base.join(file);
+            let canon = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if stack.contains(&canon) {
+                return Err(format!(
+                    "include cycle: {} includes {} again",
+                    stack
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" includes "),
+                    file
+                ));
+            }
+            let raw = std::fs::read_to_string(&path)
+                .map_err(|e| format!("cannot include {}: {}", file, e))?;
+            let mut included: serde_json::Value =
+                serde_json::from_str(&keymap::strip_jsonc(&raw))
+                    .map_err(|e| format!("cannot include {}: {}", file, e))?;
+            stack.push(canon);
+            Self::expand_includes(&mut included, path.parent().unwrap_or(Path::new(".")), stack)?;
+            stack.pop();
+            Self::merge_json(json, included);
+        }
+        // nested nodes can carry their own directives
+        if let Some(obj) = json.as_object_mut() {
+            for value in obj.values_mut() {
+                Self::expand_includes(value, base, stack)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Layer `overlay` under `json`: keys only the overlay defines are
+    /// inserted, objects merge key-wise, and on any other conflict the
+    /// including file keeps its definition.
+    fn merge_json(json: &mut serde_json::Value, overlay: serde_json::Value) {
+        let (Some(obj), serde_json::Value::Object(over)) = (json.as_object_mut(), overlay) else {
+            return;
+        };
+        for (k, v) in over {
+            match obj.get_mut(&k) {
+                None => {
+                    obj.insert(k, v);
+                }
+                Some(existing) if existing.is_object() && v.is_object() => {
+                    Self::merge_json(existing, v);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    pub fn empty() -> Self {
+        Keymap {
+            here: vec![],
+            gated: vec![],
+            hidden: vec![],
+            flavors: vec![],
+            cont: BTreeMap::new(),
+            lazy: None,
+            flat: OnceLock::new(),
+        }
+    }
+
+    fn load(json: &serde_json::Value, base: &Path) -> Option<Self> {
+        json.as_object().map(|obj| {
+            let mut here = vec![];
+            let mut gated = vec![];
+            let mut hidden = vec![];
+            let mut flavors = vec![];
+            let mut cont = BTreeMap::new();
+            if let Some(syms) = obj.get(">>").and_then(|a| a.as_array()) {
+                for s in syms {
+                    if let Some(x) = s.as_str() {
+                        here.push(Arc::from(x));
+                    } else if let Some(entry) = s.as_object()
+                        && let Some(sym) = entry.get("symbol").and_then(|s| s.as_str())
+                    {
+                        let patterns: Vec<String> = entry
+                            .get("files")
+                            .and_then(|f| f.as_array())
+                            .map(|a| {
+                                a.iter()
+                                    .filter_map(|g| g.as_str().map(str::to_string))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        let is_hidden = entry
+                            .get("hidden")
+                            .and_then(|h| h.as_bool())
+                            .unwrap_or(false);
+                        match convert::build_globset(&patterns) {
+                            Some(globs) if !patterns.is_empty() => gated.push(GatedSymbol {
+                                symbol: Arc::from(sym),
+                                globs,
+                            }),
+                            _ if is_hidden => hidden.push(Arc::from(sym)),
+                            _ => here.push(Arc::from(sym)),
+                        }
+                        // any remaining string field is an alternate output
+                        // flavor of this symbol (`latex`, `html`, ...)
+                        for (k, v) in entry {
+                            if !matches!(k.as_str(), "symbol" | "files" | "hidden")
+                                && let Some(spelling) = v.as_str()
+                            {
+                                flavors.push((
+                                    Arc::from(k.as_str()),
+                                    Arc::from(sym),
+                                    Arc::from(spelling),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            for (k, v) in obj {
+                if k != ">>"
+                    && let Some(c) = k.chars().next()
+                {
+                    // a string value points at a lazily loaded sub-file
+                    // covering the rest of the namespace
+                    if let Some(file) = v.as_str() {
+                        let mut node = Keymap::empty();
+                        node.lazy = Some(Arc::new(LazyNamespace {
+                            path: base.join(file),
+                            loaded: OnceLock::new(),
+                        }));
+                        Self::descend(&mut node, k.chars().skip(1).collect::<Vec<_>>());
+                        cont.insert(c, node);
+                    } else {
+                        Self::load(v, base).into_iter().for_each(|z| {
+                            cont.insert(c, z);
+                        });
+                    }
+                }
+            }
+            Self {
+                here,
+                gated,
+                hidden,
+                flavors,
+                cont,
+                lazy: None,
+                flat: OnceLock::new(),
+            }
+        })
+    }
+
+    /// The bundled emoji shortcode table for the `:shortcode:` input mode,
+    /// parsed once per process. Deliberately small — a full dataset can be
+    /// layered over it through the `triggers` setting.
+    pub fn embedded_emoji() -> Self {
+        static EMOJI: OnceLock<Keymap> = OnceLock::new();
+        EMOJI
+            .get_or_init(|| {
+                Self::from_flat_table(
+                    EMBEDDED_EMOJI
+                        .iter()
+                        .map(|(seq, sym)| (seq.to_string(), vec![sym.to_string()]))
+                        .collect(),
+                )
+            })
+            .clone()
+    }
+
+    /// Build a trie from flat `(sequence, symbols)` pairs, the shape the
+    /// importers in the `keymap` module produce.
+    pub fn from_flat_table(table: Vec<(String, Vec<String>)>) -> Self {
+        let mut keymap = Keymap::empty();
+        for (seq, symbols) in table {
+            keymap.insert(&seq, symbols);
+        }
+        keymap
+    }
+
+    /// Render the trie back out as the native nested JSON format; the trie
+    /// keeps its keys sorted, so the output diffs stably. Only plain entries
+    /// survive: file gates, hidden flags and lazy sub-files are features of
+    /// hand-written keymaps, not of the importers this serves.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut obj = serde_json::Map::new();
+        if !self.here.is_empty() {
+            obj.insert(
+                ">>".to_string(),
+                serde_json::json!(
+                    self.here.iter().map(|s| s.as_ref()).collect::<Vec<_>>()
+                ),
+            );
+        }
+        for (c, k) in &self.cont {
+            obj.insert(c.to_string(), k.to_json());
+        }
+        serde_json::Value::Object(obj)
+    }
+
+    /// Add a flat `(sequence, symbols)` entry, the shape the importers in
+    /// the `keymap` module produce.
+    fn insert(&mut self, sequence: &str, symbols: Vec<String>) {
+        let mut node = self;
+        node.flat.take();
+        for c in sequence.chars() {
+            node = node.cont.entry(c).or_insert_with(Keymap::empty);
+            node.flat.take();
+        }
+        for s in symbols {
+            let s: Arc<str> = s.into();
+            if !node.here.contains(&s) {
+                node.here.push(s);
+            }
+        }
+    }
+
+    /// Wrap `node` so it sits at the end of the remaining key characters.
+    fn descend(node: &mut Keymap, rest: Vec<char>) {
+        for c in rest.into_iter().rev() {
+            let inner = std::mem::replace(node, Keymap::empty());
+            node.cont.insert(c, inner);
+        }
+    }
+
+    /// The node itself, or its lazily loaded replacement.
+    fn resolve(&self) -> &Keymap {
+        match &self.lazy {
+            Some(ns) => ns.force(),
+            None => self,
+        }
+    }
+
+    pub fn lookup(&self, prefix: &str) -> Vec<Arc<str>> {
+        self.get(&mut prefix.chars())
+    }
+
+    /// Like `lookup`, but structured: every candidate under `prefix` with
+    /// its full sequence and depth, exact matches first and descendants in
+    /// breadth-first order, so depths never decrease.
+    pub fn lookup_ranked(&self, prefix: &str) -> Vec<Candidate> {
+        let mut node = self.resolve();
+        for c in prefix.chars() {
+            match node.cont.get(&c) {
+                Some(next) => node = next.resolve(),
+                None => return vec![],
+            }
+        }
+        let at = |sequence: &str, symbol: &Arc<str>, depth| Candidate {
+            sequence: sequence.to_string(),
+            symbol: symbol.clone(),
+            depth,
+        };
+        let mut ret: Vec<Candidate> = node
+            .here
+            .iter()
+            .chain(node.gated.iter().map(|g| &g.symbol))
+            .chain(node.hidden.iter())
+            .map(|s| at(prefix, s, 0))
+            .collect();
+        let mut level: Vec<(String, &Keymap)> = node
+            .cont
+            .iter()
+            .map(|(c, k)| (format!("{}{}", prefix, c), k))
+            .collect();
+        let mut depth = 1;
+        while !level.is_empty() {
+            let mut next = vec![];
+            for (seq, k) in level {
+                let k = k.resolve();
+                ret.extend(k.here.iter().map(|s| at(&seq, s, depth)));
+                next.extend(k.cont.iter().map(|(c, kk)| (format!("{}{}", seq, c), kk)));
+            }
+            level = next;
+            depth += 1;
+        }
+        ret
+    }
+
+    /// Like `lookup`, but also includes symbols gated behind per-entry
+    /// document filters when `path` (workspace-relative) matches them.
+    pub fn lookup_at(&self, prefix: &str, path: &Path) -> Vec<Arc<str>> {
+        let mut ret = self.lookup(prefix);
+        self.gated_at(prefix, path, &mut ret);
+        ret
+    }
+
+    /// Only the gated symbols under `prefix` whose filters `path` matches,
+    /// appended to `out` without duplicates — for callers that resolve the
+    /// ungated part elsewhere (e.g. from the flat arena).
+    pub fn gated_at(&self, prefix: &str, path: &Path, out: &mut Vec<Arc<str>>) {
+        fn walk(node: &Keymap, path: &Path, out: &mut Vec<Arc<str>>) {
+            let node = node.resolve();
+            for g in &node.gated {
+                if g.globs.is_match(path) && !out.contains(&g.symbol) {
+                    out.push(g.symbol.clone());
+                }
+            }
+            for k in node.cont.values() {
+                walk(k, path, out);
+            }
+        }
+        let mut node = self.resolve();
+        for c in prefix.chars() {
+            match node.cont.get(&c) {
+                Some(next) => node = next.resolve(),
+                None => return,
+            }
+        }
+        walk(node, path, out);
+    }
+
+    /// Longest sequence at the start of `input` mapping to at least one
+    /// symbol, as (chars consumed, symbols).
+    pub fn longest_match(&self, input: &str) -> Option<(usize, Vec<Arc<str>>)> {
+        let mut node = self.resolve();
+        let mut best = None;
+        for (i, c) in input.chars().enumerate() {
+            match node.cont.get(&c) {
+                Some(next) => {
+                    node = next.resolve();
+                    if !node.here.is_empty() || !node.hidden.is_empty() {
+                        let mut symbols = node.here.clone();
+                        symbols.extend(node.hidden.iter().cloned());
+                        best = Some((i + 1, symbols));
+                    }
+                }
+                None => break,
+            }
+        }
+        best
+    }
+
+    /// Merge `other` into this trie; symbols from `other` are appended after
+    /// existing ones at the same sequence, and sequences whose symbols differ
+    /// between the two sides are logged so layered keymaps stay debuggable.
+    pub fn merge(&mut self, other: Keymap) {
+        self.merge_at(other, &mut String::new());
+    }
+
+    fn merge_at(&mut self, other: Keymap, seq: &mut String) {
+        // the merge only touches this path, so only these caches go stale
+        self.flat.take();
+        if !self.here.is_empty() && !other.here.is_empty() && self.here != other.here {
+            tracing::warn!(
+                "`{}` maps to {:?} and {:?}; keeping both, earlier first",
+                seq,
+                self.here,
+                other.here
+            );
+        }
+        for s in other.here {
+            if !self.here.contains(&s) {
+                self.here.push(s);
+            }
+        }
+        self.gated.extend(other.gated);
+        for f in other.flavors {
+            if !self.flavors.contains(&f) {
+                self.flavors.push(f);
+            }
+        }
+        for s in other.hidden {
+            if !self.hidden.contains(&s) {
+                self.hidden.push(s);
+            }
+        }
+        if other.lazy.is_some() {
+            self.lazy = other.lazy;
+        }
+        for (c, k) in other.cont {
+            match self.cont.entry(c) {
+                std::collections::btree_map::Entry::Occupied(mut e) => {
+                    seq.push(c);
+                    e.get_mut().merge_at(k, seq);
+                    seq.pop();
+                }
+                std::collections::btree_map::Entry::Vacant(e) => {
+                    e.insert(k);
+                }
+            }
+        }
+    }
+
+    /// Remove every symbol mapped at exactly `sequence`, pruning branches
+    /// left empty — the blacklist half of layering, for sequences that
+    /// conflict with text the user legitimately types. Longer sequences
+    /// passing through the node survive. A lazy sub-file on the path is
+    /// forced into place first, since removal must edit real nodes.
+    pub fn remove(&mut self, sequence: &str) -> bool {
+        self.flat.take();
+        if let Some(ns) = self.lazy.take() {
+            *self = ns.force().clone();
+            self.flat.take();
+        }
+        let Some(c) = sequence.chars().next() else {
+            let had = !self.here.is_empty() || !self.gated.is_empty() || !self.hidden.is_empty();
+            self.here.clear();
+            self.gated.clear();
+            self.hidden.clear();
+            self.flavors.clear();
+            return had;
+        };
+        let rest: String = sequence.chars().skip(1).collect();
+        let Some(node) = self.cont.get_mut(&c) else {
+            return false;
+        };
+        let removed = node.remove(&rest);
+        if node.here.is_empty()
+            && node.gated.is_empty()
+            && node.hidden.is_empty()
+            && node.cont.is_empty()
+            && node.lazy.is_none()
+        {
+            self.cont.remove(&c);
+        }
+        removed
+    }
+
+    /// Case-insensitive variant of `lookup`; case-exact matches come first.
+    pub fn lookup_ci(&self, prefix: &str) -> Vec<Arc<str>> {
+        let mut ret = self.lookup(prefix);
+        let mut nodes = vec![self.resolve()];
+        for c in prefix.chars() {
+            let mut next = vec![];
+            for n in nodes {
+                let mut cases = vec![c.to_ascii_lowercase()];
+                if c.to_ascii_uppercase() != c.to_ascii_lowercase() {
+                    cases.push(c.to_ascii_uppercase());
+                }
+                for case in cases {
+                    if let Some(k) = n.cont.get(&case) {
+                        next.push(k.resolve());
+                    }
+                }
+            }
+            nodes = next;
+        }
+        for n in nodes {
+            for s in n.get(&mut "".chars()) {
+                if !ret.contains(&s) {
+                    ret.push(s);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Every alternate output spelling in the trie, as symbol → flavor →
+    /// spelling, forcing lazy namespaces. Collected once per rebuild; the
+    /// completion path substitutes spellings through the resulting map.
+    pub fn flavor_table(&self) -> HashMap<String, HashMap<String, String>> {
+        fn walk(node: &Keymap, out: &mut HashMap<String, HashMap<String, String>>) {
+            let node = node.resolve();
+            for (flavor, symbol, spelling) in &node.flavors {
+                out.entry(symbol.to_string())
+                    .or_default()
+                    .insert(flavor.to_string(), spelling.to_string());
+            }
+            for k in node.cont.values() {
+                walk(k, out);
+            }
+        }
+        let mut out = HashMap::new();
+        walk(self, &mut out);
+        out
+    }
+
+    /// Every (sequence, symbol) pair in the trie, forcing lazy namespaces.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        fn walk(node: &Keymap, prefix: &mut String, out: &mut Vec<(String, String)>) {
+            let node = node.resolve();
+            for s in &node.here {
+                out.push((prefix.clone(), s.to_string()));
+            }
+            for g in &node.gated {
+                out.push((prefix.clone(), g.symbol.to_string()));
+            }
+            for s in &node.hidden {
+                out.push((prefix.clone(), s.to_string()));
+            }
+            for (c, k) in &node.cont {
+                prefix.push(*c);
+                walk(k, prefix, out);
+                prefix.pop();
+            }
+        }
+        let mut out = vec![];
+        walk(self, &mut String::new(), &mut out);
+        out
+    }
+
+    fn get(&self, prefix: &mut Chars<'_>) -> Vec<Arc<str>> {
+        // breadth-first, so shorter continuations come before longer ones and
+        // ties fall back to key order; candidate lists stay stable between
+        // keystrokes instead of jumping around
+        fn flatten(map: &BTreeMap<char, Keymap>) -> Vec<Arc<str>> {
+            let mut ret = vec![];
+            let mut level: Vec<&Keymap> = map.values().collect();
+            while !level.is_empty() {
+                let mut next = vec![];
+                for k in level {
+                    let k = k.resolve();
+                    ret.extend(k.here.iter().cloned());
+                    next.extend(k.cont.values());
+                }
+                level = next;
+            }
+            ret
+        }
+        let node = self.resolve();
+        if let Some(c) = prefix.next() {
+            node.cont.get(&c).map_or(vec![], |k| k.get(prefix))
+        } else {
+            node.flat
+                .get_or_init(|| {
+                    // an exhausted prefix is an exact match, so hidden
+                    // symbols surface here — but never through `flatten`
+                    let mut ret = node.here.clone();
+                    ret.extend(node.hidden.iter().cloned());
+                    ret.append(&mut flatten(&node.cont));
+                    ret
+                })
+                .clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io;
+
+    #[test]
+    fn test_lookup() -> io::Result<()> {
+        let raw = std::fs::read("keymap.json")?;
+        let json: serde_json::Value =
+            serde_json::from_str(&std::string::String::from_utf8(raw).unwrap_or("".to_string()))?;
+        let keymap = Keymap::new(json);
+        assert_eq!(keymap.lookup("Gl-"), vec!["ƛ".into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flat_cache_tracks_mutation() {
+        let mut keymap =
+            Keymap::from_flat_table(vec![("to".to_string(), vec!["→".to_string()])]);
+        // the first lookup fills the per-node cache…
+        assert_eq!(keymap.lookup("t"), vec!["→".into()]);
+        // …and merging drops it along the touched path
+        keymap.merge(Keymap::from_flat_table(vec![(
+            "tb".to_string(),
+            vec!["↔".to_string()],
+        )]));
+        assert_eq!(keymap.lookup("t"), vec!["↔".into(), "→".into()]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut keymap = Keymap::from_flat_table(vec![
+            ("to".to_string(), vec!["→".to_string()]),
+            ("top".to_string(), vec!["⊤".to_string()]),
+        ]);
+        assert!(keymap.remove("to"));
+        // the exact entry is gone, longer sequences through it survive
+        assert!(keymap.lookup("to").iter().all(|s| s.as_ref() != "→"));
+        assert_eq!(keymap.lookup("top"), vec!["⊤".into()]);
+        // removing the leaf prunes the now-empty branch
+        assert!(keymap.remove("top"));
+        assert!(keymap.lookup("t").is_empty());
+        assert!(!keymap.remove("to"));
+    }
+
+    #[test]
+    fn test_lookup_ci() -> io::Result<()> {
+        let raw = std::fs::read("keymap.json")?;
+        let json: serde_json::Value = serde_json::from_slice(&raw)?;
+        let keymap = Keymap::new(json);
+        assert!(keymap.lookup("gl-").is_empty());
+        assert_eq!(keymap.lookup_ci("gl-"), vec!["ƛ".into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_order() {
+        let keymap = Keymap::from_flat_table(vec![
+            ("abb".to_string(), vec!["3".to_string()]),
+            ("ac".to_string(), vec!["2".to_string()]),
+            ("a".to_string(), vec!["0".to_string()]),
+            ("ab".to_string(), vec!["1".to_string()]),
+        ]);
+        // exact match first, then shortest continuations, then key order
+        assert_eq!(keymap.lookup("a"), vec!["0".into(), "1".into(), "2".into(), "3".into()]);
+    }
+
+    #[test]
+    fn test_gated_entries() {
+        let keymap = Keymap::with_base(serde_json::json!({
+            "q": { "e": { "d": { ">>": [ { "symbol": "∎", "files": ["**/*.agda"] } ] } } }
+        }), Path::new("."));
+        assert!(keymap.lookup("qed").is_empty());
+        assert_eq!(keymap.lookup_at("qed", Path::new("src/Main.agda")), vec!["∎".into()]);
+        assert!(keymap.lookup_at("qed", Path::new("src/main.rs")).is_empty());
+        assert!(keymap.entries().contains(&("qed".to_string(), "∎".to_string())));
+    }
+
+    #[test]
+    fn test_hidden_entries() {
+        let keymap = Keymap::with_base(serde_json::json!({
+            "q": {
+                ">>": ["ℚ"],
+                "e": { "d": { ">>": [ { "symbol": "∎", "hidden": true } ] } }
+            }
+        }), Path::new("."));
+        // not flattened into shorter-prefix candidate lists…
+        assert_eq!(keymap.lookup("q"), vec!["ℚ".into()]);
+        // …but an exact match still completes and converts
+        assert_eq!(keymap.lookup("qed"), vec!["∎".into()]);
+        assert_eq!(keymap.longest_match("qed x"), Some((3, vec!["∎".into()])));
+    }
+
+    #[test]
+    fn test_keymap_limits() {
+        let deep: String =
+            "{\"k\":".repeat(MAX_KEYMAP_DEPTH + 2) + "\"v\"" + &"}".repeat(MAX_KEYMAP_DEPTH + 2);
+        let json: serde_json::Value = serde_json::from_str(&deep).unwrap();
+        assert!(Keymap::validate(&json, 0, &mut 0).is_err());
+
+        let long_key = serde_json::json!({ "k".repeat(MAX_KEY_CHARS + 1): "v" });
+        assert!(Keymap::validate(&long_key, 0, &mut 0).is_err());
+
+        let fine = serde_json::json!({ "to": "→" });
+        assert!(Keymap::validate(&fine, 0, &mut 0).is_ok());
+    }
+
+    #[test]
+    fn test_to_json_round_trip() {
+        let keymap = Keymap::from_flat_table(vec![
+            ("to".to_string(), vec!["→".to_string()]),
+            ("top".to_string(), vec!["⊤".to_string()]),
+        ]);
+        let back = Keymap::with_base(keymap.to_json(), Path::new("."));
+        assert_eq!(back.lookup("to"), vec!["→".into(), "⊤".into()]);
+        assert_eq!(back.lookup("top"), vec!["⊤".into()]);
+    }
+
+    #[test]
+    fn test_embedded_fallback() {
+        // no external keymap at all still resolves the shipped entries
+        let keymap = Keymap::new(serde_json::Value::Null);
+        assert_eq!(keymap.lookup("Gl-"), vec!["ƛ".into()]);
+    }
+
+    #[test]
+    fn test_flavor_table() {
+        let json = serde_json::json!({
+            "t": { "o": { ">>": [
+                { "symbol": "→", "latex": "\\rightarrow", "html": "&rarr;" }
+            ] } }
+        });
+        let keymap = Keymap::with_base(json, Path::new("."));
+        // lookup still deals in the symbol; flavors sit in the side table
+        assert_eq!(keymap.lookup("to"), vec!["→".into()]);
+        let flavors = keymap.flavor_table();
+        assert_eq!(flavors["→"]["latex"], "\\rightarrow");
+        assert_eq!(flavors["→"]["html"], "&rarr;");
+    }
+
+    #[test]
+    fn test_lookup_ranked() {
+        let keymap = Keymap::embedded();
+        let ranked = keymap.lookup_ranked("to");
+        assert!(!ranked.is_empty());
+        // the exact sequence's own symbol leads
+        assert_eq!(ranked[0].symbol, keymap.lookup("to")[0]);
+        // depths never decrease, and each one records the remaining length
+        let mut last = 0;
+        for c in &ranked {
+            assert!(c.sequence.starts_with("to"), "{}", c.sequence);
+            assert_eq!(c.depth, c.sequence.chars().count() - 2);
+            assert!(c.depth >= last);
+            last = c.depth;
+        }
+    }
+
+    #[test]
+    fn test_include_directive() -> io::Result<()> {
+        let dir = std::env::temp_dir().join("aim-lsp-test-include");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("greek.json"),
+            r#"{ "$include": ["tail.json"], "a": { ">>": ["α"] } }"#,
+        )?;
+        std::fs::write(dir.join("tail.json"), r#"{ "b": { ">>": ["β"] } }"#)?;
+        std::fs::write(
+            dir.join("main.json"),
+            r#"{ "$include": ["greek.json"], "a": { ">>": ["∀"] } }"#,
+        )?;
+        let keymap = Keymap::from_file(&dir.join("main.json")).unwrap();
+        // the including file wins on conflicts; includes of includes resolve
+        assert_eq!(keymap.lookup("a"), vec!["∀".into()]);
+        assert_eq!(keymap.lookup("b"), vec!["β".into()]);
+        // a file including itself (transitively) is reported, not a hang
+        std::fs::write(dir.join("x.json"), r#"{ "$include": ["y.json"] }"#)?;
+        std::fs::write(dir.join("y.json"), r#"{ "$include": ["x.json"] }"#)?;
+        let err = Keymap::from_file(&dir.join("x.json")).unwrap_err();
+        assert!(err.contains("include cycle"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_namespace() -> io::Result<()> {
+        let dir = std::env::temp_dir().join("aim-lsp-test-lazy");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("emoji.json"),
+            r#"{ "t": { "a": { "d": { "a": { ">>": ["🎉"] } } } } }"#,
+        )?;
+        let index = serde_json::json!({ "emoji:": "emoji.json" });
+        let keymap = Keymap::with_base(index, &dir);
+        assert_eq!(keymap.lookup("emoji:tada"), vec!["🎉".into()]);
+        Ok(())
+    }
+}
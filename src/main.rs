@@ -1,184 +1,3973 @@
+use aim_lsp::{
+    Keymap, cache, cjk, config, context, convert, diag, flat, fuzzy, greek, keymap, notebook,
+    requests, reverse, stats, text, unicode, xref,
+};
 use dashmap::DashMap;
 use std::collections::HashMap;
-use std::str::Chars;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 
+/// Runtime counters behind `aim/stats`, cheap enough to bump on the
+/// completion hot path.
+#[derive(Debug, Default)]
+struct Metrics {
+    /// Completion requests answered with a candidate list.
+    completions: std::sync::atomic::AtomicU64,
+    /// Hits and misses across the lazily built caches.
+    cache_hits: std::sync::atomic::AtomicU64,
+    cache_misses: std::sync::atomic::AtomicU64,
+    /// Recent completion latencies in microseconds, a bounded window so a
+    /// long session never grows it without limit.
+    latencies_us: std::sync::Mutex<std::collections::VecDeque<u64>>,
+}
+
+impl Metrics {
+    const WINDOW: usize = 1024;
+
+    fn hit(&self) {
+        self.cache_hits
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.cache_misses
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn record(&self, started: std::time::Instant) {
+        self.completions
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut window = self.latencies_us.lock().unwrap();
+        if window.len() >= Self::WINDOW {
+            window.pop_front();
+        }
+        window.push_back(started.elapsed().as_micros() as u64);
+    }
+
+    /// Mean and 95th-percentile latency over the recorded window.
+    fn latency(&self) -> (u64, u64) {
+        let window = self.latencies_us.lock().unwrap();
+        if window.is_empty() {
+            return (0, 0);
+        }
+        let mut sorted: Vec<u64> = window.iter().copied().collect();
+        sorted.sort_unstable();
+        let avg = sorted.iter().sum::<u64>() / sorted.len() as u64;
+        let p95 = sorted[(sorted.len() - 1) * 95 / 100];
+        (avg, p95)
+    }
+}
+
+/// The last conversion applied to a document, for the candidate-cycling
+/// commands: where the symbol sits, the candidates of its sequence, and
+/// which of them is currently inserted.
 #[derive(Debug, Clone)]
-struct Keymap {
-    here: Vec<String>,
-    cont: HashMap<char, Keymap>,
+struct LastConversion {
+    line: u32,
+    /// Char column where the inserted symbol starts.
+    start: u32,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+/// One editor session. In daemon mode several of these live side by side,
+/// one per connection: everything except the fields cloned out of
+/// `SharedState` (keymap, compiled mapping, reverse index, usage store) is
+/// session-local, so editors attached to the same daemon never see each
+/// other's documents, settings or caches.
+#[derive(Debug)]
+struct Backend {
+    client: Client,
+    /// The active keymap; swappable so `initializationOptions.keymapPath`
+    /// can point a session at its own file.
+    keymap: RwLock<Arc<Keymap>>,
+    /// Memory-mapped precompiled keymap; when present, completions read
+    /// straight from the mapping instead of the trie.
+    compiled: Option<Arc<cache::CompiledKeymap>>,
+    reverse: Arc<reverse::ReverseIndex>,
+    documents: Arc<DashMap<Url, String>>,
+    /// Line-start index per stored document, kept in step with `documents`
+    /// by `store_document`, so the completion hot path extracts the current
+    /// line without rescanning from the top.
+    line_index: DashMap<Url, text::LineIndex>,
+    /// Latest version seen per document; `didChange` notifications carrying
+    /// an older version than this are discarded.
+    versions: DashMap<Url, i32>,
+    /// languageId per open document, from `didOpen`.
+    languages: DashMap<Url, String>,
+    /// Per-language keymaps, loaded on first use.
+    lang_keymaps: DashMap<String, Arc<Keymap>>,
+    /// Keymap files loaded on demand (fallback chain), cached by path.
+    file_keymaps: DashMap<PathBuf, Arc<Keymap>>,
+    /// Workspace-folder keymap overlays in multi-root workspaces, loaded on
+    /// first use and keyed by the owning folder.
+    folder_keymaps: DashMap<PathBuf, Arc<Keymap>>,
+    stats: Arc<stats::UsageStats>,
+    /// Session-local runtime counters, served by `aim/stats`.
+    metrics: Metrics,
+    /// Alternate output spellings from the active keymap, symbol → flavor →
+    /// spelling, refreshed on every rebuild.
+    flavors: RwLock<HashMap<String, HashMap<String, String>>>,
+    /// Pinyin table, loaded on first use of the leader.
+    pinyin: OnceLock<Option<cjk::SyllableTable>>,
+    /// Zhuyin table, same lifecycle as the pinyin one.
+    zhuyin: OnceLock<Option<cjk::SyllableTable>>,
+    /// Imported Cangjie table, same lifecycle as the pinyin one.
+    cangjie: OnceLock<Option<cjk::SyllableTable>>,
+    /// Fuzzy lookup index over the active keymap, built on first fuzzy query
+    /// and dropped on keymap rebuilds.
+    fuzzy_index: RwLock<Option<Arc<fuzzy::FuzzyIndex>>>,
+    /// Flat arena rendering of the active keymap, built on first lookup
+    /// after every rebuild; the per-keystroke descent runs on this.
+    flat_trie: RwLock<Option<Arc<flat::FlatTrie>>>,
+    /// Which layer contributed each (sequence, symbol) pair, rebuilt along
+    /// with the keymap; `aim/keymap` reports it.
+    keymap_origins: RwLock<HashMap<(String, String), String>>,
+    /// One trie per extra trigger from the `triggers` setting, rebuilt
+    /// along with the main keymap.
+    trigger_keymaps: RwLock<HashMap<char, Arc<Keymap>>>,
+    /// What went wrong during the last keymap rebuild, if anything;
+    /// `aim/status` carries it so statusbars can flag a broken setup.
+    last_rebuild_error: RwLock<Option<String>>,
+    /// Carried over from startup: the keymap file that could not be loaded,
+    /// surfaced via `window/showMessage` during `initialize`.
+    startup_error: Option<String>,
+    /// Per-document last conversion, rotated by `aim.nextCandidate` /
+    /// `aim.prevCandidate`.
+    last_conversion: DashMap<Url, LastConversion>,
+    /// The selected keymap profile, if any; its files join the layering.
+    profile: RwLock<Option<String>>,
+    /// `initializationOptions` as received, kept so later
+    /// `didChangeConfiguration` overlays re-resolve against the same base.
+    init_options: RwLock<Option<serde_json::Value>>,
+    capabilities: OnceLock<ClientCapabilities>,
+    /// Position encoding negotiated in `initialize`; UTF-16 until a client
+    /// asks for something else.
+    encoding: OnceLock<text::Encoding>,
+    settings: RwLock<config::Settings>,
+    roots: RwLock<Vec<PathBuf>>,
+    /// Last published lint set per document; unchanged sets aren't resent.
+    published_diags: Arc<DashMap<Url, Vec<Diagnostic>>>,
+    /// Per-document revision counter; a debounced lint pass only publishes
+    /// when it still holds the latest revision after the delay.
+    diag_revision: Arc<DashMap<Url, u64>>,
+}
+
+impl Backend {
+    fn keymap(&self) -> Arc<Keymap> {
+        self.keymap.read().unwrap().clone()
+    }
+
+    fn encoding(&self) -> text::Encoding {
+        self.encoding.get().copied().unwrap_or_default()
+    }
+
+    /// The configured escape-sequence trigger (`\` unless overridden).
+    fn trigger(&self) -> char {
+        self.settings
+            .read()
+            .unwrap()
+            .trigger
+            .chars()
+            .next()
+            .unwrap_or('\\')
+    }
+
+    /// A symbol normalized per the `normalization` setting, applied to
+    /// everything the server inserts so one file never mixes composed and
+    /// decomposed forms.
+    fn normalize(&self, sym: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        match self.settings.read().unwrap().normalization.as_deref() {
+            Some("nfc") => sym.nfc().collect(),
+            Some("nfd") => sym.nfd().collect(),
+            _ => sym.to_string(),
+        }
+    }
+
+    /// Scan `text` with the active keymap and trigger, normalizing the
+    /// replacement symbols.
+    fn scan_document(&self, text: &str) -> Vec<convert::Replacement> {
+        let mut found = convert::scan_with(&self.keymap(), text, self.trigger());
+        for r in &mut found {
+            r.symbol = self.normalize(&r.symbol);
+        }
+        found
+    }
+
+    /// The nearest enabled trigger before the cursor and the partial
+    /// sequence after it. Extra triggers carry the trie they're bound to;
+    /// the main trigger dispatches to the usual keymap machinery.
+    ///
+    /// Sequences may contain the trigger character itself (`\\` → `＼`,
+    /// `\\n` → `␤`): after taking the rightmost trigger as the default, the
+    /// scan keeps walking left and prefers a longer sequence whenever the
+    /// keymap actually knows that spelling. With `escapeDoubledTrigger` on
+    /// (the default) a doubled trigger stays an escaped literal and the
+    /// rightmost trigger decides alone — the two readings of `\\` are
+    /// mutually exclusive.
+    fn nearest_trigger<'a>(&self, line: &'a str) -> Option<(char, &'a str, Option<Arc<Keymap>>)> {
+        let main = self.trigger();
+        let extra = self.trigger_keymaps.read().unwrap();
+        let settings = self.settings.read().unwrap();
+        let boundary = |seq: &str| {
+            // the token ends at whitespace or a configured boundary; a
+            // prefix crossing one is prose, not a pending sequence
+            seq.chars()
+                .any(|b| b.is_whitespace() || settings.boundary_chars.contains(b))
+        };
+        if settings.escape_doubled_trigger {
+            let (at, c) = line
+                .char_indices()
+                .rev()
+                .find(|(_, c)| *c == main || extra.contains_key(c))?;
+            // a doubled trigger is an escaped literal; count the whole run
+            // so `\\\x` (escaped backslash, then a real trigger) still
+            // completes
+            let run = line[..at].chars().rev().take_while(|p| *p == c).count();
+            if run % 2 == 1 {
+                return None;
+            }
+            let seq = &line[at + c.len_utf8()..];
+            return (!boundary(seq)).then(|| (c, seq, extra.get(&c).cloned()));
+        }
+        let mut found: Option<(char, &'a str, Option<Arc<Keymap>>)> = None;
+        for (at, c) in line
+            .char_indices()
+            .rev()
+            .filter(|(_, c)| *c == main || extra.contains_key(c))
+        {
+            let seq = &line[at + c.len_utf8()..];
+            // anything further left crosses the same boundary
+            if boundary(seq) {
+                break;
+            }
+            match &found {
+                // the rightmost trigger is the default reading
+                None => found = Some((c, seq, extra.get(&c).cloned())),
+                Some(_) => {
+                    // a longer sequence swallowing the trigger wins only
+                    // when some entry actually starts with it
+                    let bound = extra.get(&c).cloned();
+                    let known = match &bound {
+                        Some(trie) => !trie.lookup(seq).is_empty(),
+                        None => !self.keymap().lookup(seq).is_empty(),
+                    };
+                    if known {
+                        found = Some((c, seq, bound));
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    fn fuzzy_index(&self) -> Arc<fuzzy::FuzzyIndex> {
+        if let Some(index) = self.fuzzy_index.read().unwrap().clone() {
+            self.metrics.hit();
+            return index;
+        }
+        self.metrics.miss();
+        let index = Arc::new(fuzzy::FuzzyIndex::new(&self.keymap().entries()));
+        *self.fuzzy_index.write().unwrap() = Some(index.clone());
+        index
+    }
+
+    fn flat_trie(&self) -> Arc<flat::FlatTrie> {
+        if let Some(trie) = self.flat_trie.read().unwrap().clone() {
+            self.metrics.hit();
+            return trie;
+        }
+        self.metrics.miss();
+        let trie = Arc::new(flat::FlatTrie::build(&self.keymap()));
+        *self.flat_trie.write().unwrap() = Some(trie.clone());
+        trie
+    }
+
+    /// Every file the active keymap is layered from, in merge order: the
+    /// startup file (or `keymapPath`), the configured `keymapFiles`, and the
+    /// first workspace-local keymap of each root. These are also the paths
+    /// watched for hot reloading.
+    fn keymap_sources(&self) -> Vec<PathBuf> {
+        let settings = self.settings.read().unwrap();
+        let mut sources = vec![
+            settings
+                .keymap_path
+                .clone()
+                .unwrap_or_else(|| config::Env::load().keymap_path()),
+        ];
+        sources.extend(settings.keymap_files.iter().cloned());
+        if let Some(name) = self.profile.read().unwrap().as_ref()
+            && let Some(files) = settings.profiles.get(name)
+        {
+            sources.extend(files.iter().cloned());
+        }
+        drop(settings);
+        // a single root's local keymap merges globally; with several roots
+        // each folder's file applies only to its own documents, as a
+        // per-completion overlay resolved in `folder_keymap_for`
+        let roots = self.roots.read().unwrap();
+        if roots.len() == 1
+            && let Some(local) = [roots[0].join(".aim.json"), roots[0].join(".aim/keymap.json")]
+                .into_iter()
+                .find(|c| c.is_file())
+        {
+            sources.push(local);
+        }
+        sources
+    }
+
+    /// Under `strictKeymap`, run the `check` lint over a file that loaded
+    /// and surface whatever the forgiving parser silently dropped.
+    async fn report_keymap_problems(&self, path: &Path) {
+        let problems = lint_keymap(path);
+        if problems.is_empty() {
+            return;
+        }
+        for p in &problems {
+            tracing::warn!("{}: {}", path.display(), p);
+        }
+        self.client
+            .show_message(
+                MessageType::WARNING,
+                format!(
+                    "aim: {} problem(s) in {}: {}",
+                    problems.len(),
+                    path.display(),
+                    problems.join("; ")
+                ),
+            )
+            .await;
+    }
+
+    /// Recompute the active keymap from every layer over the embedded table,
+    /// on initialize and again whenever a watched source file changes, so
+    /// keymap edits land without restarting the server.
+    async fn rebuild_keymap(&self) {
+        let (explicit, strict) = {
+            let settings = self.settings.read().unwrap();
+            (settings.keymap_path.is_some(), settings.strict_keymap)
+        };
+        // a $/progress spinner while the layers load: emoji + agda + CJK
+        // tables add up to tens of thousands of entries, and initialize
+        // looks hung without it; clients that reject the token creation
+        // simply get no reports
+        let token = NumberOrString::String("aim/keymap".to_string());
+        let progress = self
+            .client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .is_ok();
+        if progress {
+            self.report_progress(
+                &token,
+                WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                    title: "loading keymaps".to_string(),
+                    ..Default::default()
+                }),
+            )
+            .await;
+        }
+        let mut keymap = Keymap::embedded();
+        let mut failed: Vec<String> = vec![];
+        let mut origins: HashMap<(String, String), String> = keymap
+            .entries()
+            .into_iter()
+            .map(|entry| (entry, "embedded".to_string()))
+            .collect();
+        for (i, source) in self.keymap_sources().into_iter().enumerate() {
+            if progress {
+                self.report_progress(
+                    &token,
+                    WorkDoneProgress::Report(WorkDoneProgressReport {
+                        message: Some(source.display().to_string()),
+                        ..Default::default()
+                    }),
+                )
+                .await;
+            }
+            match Keymap::from_file(&source) {
+                Ok(layer) => {
+                    if strict {
+                        self.report_keymap_problems(&source).await;
+                    }
+                    for entry in layer.entries() {
+                        origins
+                            .entry(entry)
+                            .or_insert_with(|| source.display().to_string());
+                    }
+                    keymap.merge(layer)
+                }
+                // the implicit startup file is allowed to be absent (the
+                // embedded keymap covers that); everything configured is not
+                Err(e) if i > 0 || explicit => {
+                    failed.push(source.display().to_string());
+                    self.client
+                        .show_message(
+                            MessageType::ERROR,
+                            format!("aim: cannot load keymap {}: {}", source.display(), e),
+                        )
+                        .await;
+                }
+                Err(_) => {}
+            }
+        }
+        // declarative families stamp out whole entry ranges (subscripts,
+        // superscripts, math alphabets) without listing every member
+        let families = self.settings.read().unwrap().families.clone();
+        if !families.is_empty() {
+            let generated = Keymap::from_flat_table(keymap::expand_families(&families));
+            for entry in generated.entries() {
+                origins
+                    .entry(entry)
+                    .or_insert_with(|| "families".to_string());
+            }
+            keymap.merge(generated);
+        }
+        // the blacklist runs last, after every layer has merged, so it
+        // silences built-ins and layered files alike
+        let disabled = self.settings.read().unwrap().disabled_sequences.clone();
+        for seq in &disabled {
+            if keymap.remove(seq) {
+                origins.retain(|(s, _), _| s != seq);
+            } else {
+                tracing::warn!("disabledSequences: `{}` maps to nothing", seq);
+            }
+        }
+        // every extra trigger gets its own trie from the files bound to it
+        let mut trigger_keymaps = HashMap::new();
+        let (triggers, emoji) = {
+            let settings = self.settings.read().unwrap();
+            (settings.triggers.clone(), settings.emoji)
+        };
+        for (prefix, files) in triggers {
+            let Some(c) = prefix.chars().next() else {
+                continue;
+            };
+            // the emoji mode seeds `:` with the bundled shortcodes; files
+            // bound to it merge over them
+            let mut bound = if emoji && c == ':' {
+                Keymap::embedded_emoji()
+            } else {
+                Keymap::empty()
+            };
+            for file in &files {
+                match Keymap::from_file(file) {
+                    Ok(layer) => {
+                        if strict {
+                            self.report_keymap_problems(file).await;
+                        }
+                        bound.merge(layer)
+                    }
+                    Err(e) => {
+                        failed.push(file.display().to_string());
+                        self.client
+                            .show_message(
+                                MessageType::ERROR,
+                                format!("aim: cannot load keymap {}: {}", file.display(), e),
+                            )
+                            .await;
+                    }
+                }
+            }
+            trigger_keymaps.insert(c, Arc::new(bound));
+        }
+        if emoji {
+            trigger_keymaps
+                .entry(':')
+                .or_insert_with(|| Arc::new(Keymap::embedded_emoji()));
+        }
+        tracing::info!(
+            "keymap rebuilt: {} entries, {} extra triggers",
+            keymap.entries().len(),
+            trigger_keymaps.len()
+        );
+        if progress {
+            self.report_progress(
+                &token,
+                WorkDoneProgress::End(WorkDoneProgressEnd {
+                    message: Some(format!("{} entries", keymap.entries().len())),
+                }),
+            )
+            .await;
+        }
+        *self.trigger_keymaps.write().unwrap() = trigger_keymaps;
+        *self.flavors.write().unwrap() = keymap.flavor_table();
+        *self.keymap.write().unwrap() = Arc::new(keymap);
+        *self.keymap_origins.write().unwrap() = origins;
+        *self.last_rebuild_error.write().unwrap() =
+            (!failed.is_empty()).then(|| format!("failed to load {}", failed.join(", ")));
+        // cached per-language, per-file and per-folder keymaps reload lazily
+        self.lang_keymaps.clear();
+        self.file_keymaps.clear();
+        self.folder_keymaps.clear();
+        *self.fuzzy_index.write().unwrap() = None;
+        *self.flat_trie.write().unwrap() = None;
+        // every rebuild pushes a fresh status, so statusbar extensions track
+        // profile switches, hot reloads and load failures without polling
+        self.client
+            .send_notification::<requests::StatusNotification>(self.status_snapshot())
+            .await;
+    }
+
+    fn completion_options() -> CompletionOptions {
+        CompletionOptions {
+            resolve_provider: Some(true),
+            trigger_characters: Some(('!'..='~').map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// One-type legend: every token is a pending escape sequence, reported
+    /// as `macro` so stock themes highlight it without extra configuration.
+    fn semantic_tokens_options() -> SemanticTokensOptions {
+        SemanticTokensOptions {
+            legend: SemanticTokensLegend {
+                token_types: vec![SemanticTokenType::MACRO],
+                token_modifiers: vec![],
+            },
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+            ..Default::default()
+        }
+    }
+
+    fn supports_dynamic_completion(&self) -> bool {
+        self.capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.dynamic_registration)
+            .unwrap_or(false)
+    }
+
+    fn supports_label_details(&self) -> bool {
+        self.capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|i| i.label_details_support)
+            .unwrap_or(false)
+    }
+
+    fn supports_snippets(&self) -> bool {
+        self.capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|i| i.snippet_support)
+            .unwrap_or(false)
+    }
+
+    /// A client that doesn't announce any completion capability at all is
+    /// taken as minimal: it gets plain `insertText` items instead of text
+    /// edits, which every client understands.
+    fn supports_text_edit(&self) -> bool {
+        self.capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .map(|t| t.completion.is_some())
+            .unwrap_or(false)
+    }
+
+    /// The richest markup the client lists for hover content; plaintext
+    /// when markdown was never advertised.
+    fn hover_markup(&self) -> MarkupKind {
+        let markdown = self
+            .capabilities
+            .get()
+            .and_then(|c| c.text_document.as_ref())
+            .and_then(|t| t.hover.as_ref())
+            .and_then(|h| h.content_format.as_ref())
+            .is_some_and(|f| f.contains(&MarkupKind::Markdown));
+        if markdown {
+            MarkupKind::Markdown
+        } else {
+            MarkupKind::PlainText
+        }
+    }
+
+    fn supports_change_annotations(&self) -> bool {
+        self.capabilities
+            .get()
+            .and_then(|c| c.workspace.as_ref())
+            .and_then(|w| w.workspace_edit.as_ref())
+            .and_then(|w| w.change_annotation_support.as_ref())
+            .is_some()
+    }
+
+    /// Whether `uri` matches any of the `expandOnSave` globs, relative to a
+    /// workspace root when it is under one. Non-file schemes (`untitled:`,
+    /// notebook cells, ...) are matched on their raw path component.
+    fn expands_on_save(&self, uri: &Url) -> bool {
+        let globs = {
+            let settings = self.settings.read().unwrap();
+            if settings.convert_on_save {
+                return true;
+            }
+            convert::build_globset(&settings.expand_on_save)
+        };
+        let Some(globs) = globs else {
+            return false;
+        };
+        globs.is_match(self.relative_path(uri))
+    }
+
+    /// `uri` as a path relative to its workspace root — the form every glob
+    /// in the configuration is matched against. Non-file schemes
+    /// (`untitled:`, `vscode-notebook-cell:`, ...) have no on-disk path and
+    /// fall back to the raw path component, so scheme-agnostic globs like
+    /// `**/*.ipynb` still apply to them.
+    fn relative_path(&self, uri: &Url) -> PathBuf {
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let roots = self.roots.read().unwrap();
+        roots
+            .iter()
+            .find_map(|r| path.strip_prefix(r).ok())
+            .unwrap_or(&path)
+            .to_path_buf()
+    }
+
+    /// Candidates from the pinyin mode, when the prefix carries its leader.
+    fn pinyin_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let (leader, path) = {
+            let settings = self.settings.read().unwrap();
+            (settings.pinyin_leader.clone(), settings.pinyin_table.clone()?)
+        };
+        let rest = prefix.strip_prefix(&leader)?;
+        if rest.is_empty() {
+            return None;
+        }
+        self.pinyin
+            .get_or_init(|| cjk::SyllableTable::load(&path))
+            .as_ref()
+            .map(|t| t.candidates(rest))
+    }
+
+    /// Candidates from the zhuyin mode, when the prefix carries its leader.
+    fn zhuyin_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let (leader, path) = {
+            let settings = self.settings.read().unwrap();
+            (settings.zhuyin_leader.clone(), settings.zhuyin_table.clone()?)
+        };
+        let rest = prefix.strip_prefix(&leader)?;
+        if rest.is_empty() {
+            return None;
+        }
+        self.zhuyin
+            .get_or_init(|| cjk::SyllableTable::load(&path))
+            .as_ref()
+            .map(|t| t.candidates(rest))
+    }
+
+    /// Candidates from an imported Cangjie table, behind its leader.
+    fn cangjie_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let (leader, path) = {
+            let settings = self.settings.read().unwrap();
+            (settings.cangjie_leader.clone(), settings.cangjie_table.clone()?)
+        };
+        let rest = prefix.strip_prefix(&leader)?;
+        if rest.is_empty() {
+            return None;
+        }
+        self.cangjie
+            .get_or_init(|| cjk::SyllableTable::load_cangjie(&path))
+            .as_ref()
+            .map(|t| t.candidates(&rest.to_lowercase()))
+    }
+
+    /// Romaji → kana conversion behind its leaders.
+    fn kana_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let (hira, kata) = {
+            let settings = self.settings.read().unwrap();
+            (
+                settings.romaji_leader.clone(),
+                settings.katakana_leader.clone(),
+            )
+        };
+        let (rest, katakana) = match prefix.strip_prefix(&kata) {
+            Some(rest) => (rest, true),
+            None => (prefix.strip_prefix(&hira)?, false),
+        };
+        if rest.is_empty() {
+            return None;
+        }
+        cjk::romaji_to_kana(rest, katakana).map(|kana| vec![kana])
+    }
+
+    /// Romanized jamo → composed Hangul behind its leader.
+    fn hangul_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let leader = self.settings.read().unwrap().hangul_leader.clone();
+        let rest = prefix.strip_prefix(&leader)?;
+        if rest.is_empty() {
+            return None;
+        }
+        cjk::jamo_to_hangul(rest).map(|hangul| vec![hangul])
+    }
+
+    /// Greek transliteration mode: the leader plus beta-code Latin letters,
+    /// e.g. `\Ga` → α, `\Gth` → θ. Inputs the rule table can't fully
+    /// transliterate fall through to the trie, so the mode composes with
+    /// hand-written `G`-prefixed entries instead of shadowing them.
+    fn greek_candidates(&self, prefix: &str) -> Option<Vec<String>> {
+        let leader = self.settings.read().unwrap().greek_leader.clone();
+        if leader.is_empty() {
+            return None;
+        }
+        let rest = prefix.strip_prefix(&leader)?;
+        if rest.is_empty() {
+            return None;
+        }
+        greek::transliterate(rest).map(|s| vec![s])
+    }
+
+    /// Recompute and publish lints for a document after the configured
+    /// debounce delay. Passes superseded by a newer edit are dropped, and an
+    /// unchanged result set isn't resent to the client.
+    fn schedule_diagnostics(&self, uri: Url) {
+        let settings = self.settings.read().unwrap().clone();
+        let delay = settings.diagnostics_delay_ms;
+        let rev = {
+            let mut entry = self.diag_revision.entry(uri.clone()).or_insert(0);
+            *entry += 1;
+            *entry
+        };
+        let client = self.client.clone();
+        let keymap = self.keymap();
+        let documents = self.documents.clone();
+        let published = self.published_diags.clone();
+        let revisions = self.diag_revision.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            if revisions.get(&uri).map(|r| *r) != Some(rev) {
+                return;
+            }
+            let Some(text) = documents.get(&uri).map(|d| d.clone()) else {
+                return;
+            };
+            let diags = diag::collect(&keymap, &text, &settings);
+            if published.get(&uri).map(|d| d.clone()).as_ref() == Some(&diags) {
+                return;
+            }
+            published.insert(uri.clone(), diags.clone());
+            client.publish_diagnostics(uri, diags, None).await;
+        });
+    }
+
+    fn load_keymap_file(&self, path: &Path) -> Arc<Keymap> {
+        if let Some(k) = self.file_keymaps.get(path) {
+            return k.clone();
+        }
+        // digraph tables honor the session's configured prefix
+        let loaded = if path.extension().is_some_and(|e| e == "digraphs") {
+            let prefix = self.settings.read().unwrap().digraph_prefix.clone();
+            std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .map(|text| Keymap::from_flat_table(keymap::parse_digraphs(&text, &prefix)))
+        } else {
+            Keymap::from_file(path)
+        };
+        let keymap = Arc::new(loaded.unwrap_or_else(|e| {
+            tracing::warn!("rejected keymap {}: {}", path.display(), e);
+            Keymap::empty()
+        }));
+        self.file_keymaps.insert(path.to_path_buf(), keymap.clone());
+        keymap
+    }
+
+    /// Whether the server is enabled for `uri`, per the `languages`
+    /// setting; untracked documents (never opened) count as enabled.
+    fn enabled_for(&self, uri: &Url) -> bool {
+        let languages = &self.settings.read().unwrap().languages;
+        if languages.is_empty() {
+            return true;
+        }
+        self.languages
+            .get(uri)
+            .map(|l| languages.contains(&l))
+            .unwrap_or(true)
+    }
+
+    /// Store a document and the line-start index derived from it; the two
+    /// always change together.
+    fn store_document(&self, uri: Url, document: String) {
+        self.line_index
+            .insert(uri.clone(), text::LineIndex::new(&document));
+        self.documents.insert(uri, document);
+    }
+
+    /// One line of `text` (the stored copy of `uri`), via the line index
+    /// when there is one and the slow scan when there isn't — documents can
+    /// land in the map without passing `store_document` in tests.
+    fn current_line<'a>(&self, uri: &Url, text: &'a str, line: u32) -> Option<&'a str> {
+        match self.line_index.get(uri) {
+            Some(index) => index.line(text, line as usize),
+            None => text.lines().nth(line as usize),
+        }
+    }
+
+    /// Remember the conversion just applied to `uri`, so the cycling
+    /// commands know what to rotate and where.
+    fn remember_conversion(&self, uri: &Url, r: &convert::Replacement, candidates: Vec<String>) {
+        let index = candidates.iter().position(|c| *c == r.symbol).unwrap_or(0);
+        self.last_conversion.insert(
+            uri.clone(),
+            LastConversion {
+                line: r.line,
+                start: r.start,
+                candidates,
+                index,
+            },
+        );
+    }
+
+    /// The keymap bound to `uri`'s languageId, if one is configured.
+    fn keymap_for(&self, uri: &Url) -> Option<Arc<Keymap>> {
+        let lang = self.languages.get(uri)?.clone();
+        if let Some(k) = self.lang_keymaps.get(&lang) {
+            self.metrics.hit();
+            return Some(k.clone());
+        }
+        self.metrics.miss();
+        let files = self.settings.read().unwrap().language_keymaps.get(&lang)?.clone();
+        let mut keymap = Keymap::empty();
+        for file in files {
+            match Keymap::from_file(&file) {
+                Ok(loaded) => keymap.merge(loaded),
+                Err(e) => tracing::warn!("rejected keymap {}: {}", file.display(), e),
+            }
+        }
+        let keymap = Arc::new(keymap);
+        self.lang_keymaps.insert(lang, keymap.clone());
+        Some(keymap)
+    }
+
+    /// The owning workspace folder's local keymap for `uri`, in multi-root
+    /// workspaces: the deepest folder containing the document wins, and its
+    /// `.aim.json` entries rank ahead of the shared layers. A single-root
+    /// workspace merges its local keymap globally instead. Non-file schemes
+    /// resolve on their raw path component, so a notebook cell whose path
+    /// sits under a folder still gets that folder's overrides; untitled
+    /// buffers belong to no folder and see only the shared layers.
+    fn folder_keymap_for(&self, uri: &Url) -> Option<Arc<Keymap>> {
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let root = {
+            let roots = self.roots.read().unwrap();
+            if roots.len() < 2 {
+                return None;
+            }
+            roots
+                .iter()
+                .filter(|r| path.starts_with(r))
+                .max_by_key(|r| r.components().count())?
+                .clone()
+        };
+        if let Some(k) = self.folder_keymaps.get(&root) {
+            self.metrics.hit();
+            return Some(k.clone());
+        }
+        self.metrics.miss();
+        let local = [root.join(".aim.json"), root.join(".aim/keymap.json")]
+            .into_iter()
+            .find(|c| c.is_file())?;
+        let keymap = match Keymap::from_file(&local) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                tracing::warn!("rejected keymap {}: {}", local.display(), e);
+                Keymap::empty()
+            }
+        };
+        let keymap = Arc::new(keymap);
+        self.folder_keymaps.insert(root, keymap.clone());
+        Some(keymap)
+    }
+
+    /// `aim/exportStats`: dump usage statistics as JSON.
+    async fn export_stats(&self) -> Result<stats::StatsExport> {
+        Ok(self.stats.export(&self.keymap().entries()))
+    }
+
+    /// `aim/scan`: every convertible sequence in a document's stored text,
+    /// with ranges in the negotiated encoding.
+    async fn scan_request(&self, params: requests::ScanParams) -> Result<Vec<requests::ScanEntry>> {
+        let Some(document) = self.documents.get(&params.uri).map(|d| d.clone()) else {
+            return Ok(vec![]);
+        };
+        let keymap = self.keymap();
+        let enc = self.encoding();
+        Ok(self
+            .scan_document(&document)
+            .into_iter()
+            .map(|r| {
+                let line = document.lines().nth(r.line as usize).unwrap_or("");
+                let candidates = keymap
+                    .longest_match(&r.sequence)
+                    .map(|(_, symbols)| symbols.iter().map(|s| s.to_string()).collect())
+                    .unwrap_or_else(|| vec![r.symbol.clone()]);
+                requests::ScanEntry {
+                    range: convert::to_text_edit(line, &r, enc).range,
+                    sequence: r.sequence,
+                    candidates,
+                }
+            })
+            .collect())
+    }
+
+    /// `aim/stats`: the session's runtime metrics.
+    async fn runtime_stats(&self) -> Result<requests::Metrics> {
+        let mut entries_per_source: HashMap<String, usize> = HashMap::new();
+        for origin in self.keymap_origins.read().unwrap().values() {
+            *entries_per_source.entry(origin.clone()).or_default() += 1;
+        }
+        let (avg, p95) = self.metrics.latency();
+        let relaxed = std::sync::atomic::Ordering::Relaxed;
+        Ok(requests::Metrics {
+            entries_per_source,
+            completions: self.metrics.completions.load(relaxed),
+            completion_latency_avg_us: avg,
+            completion_latency_p95_us: p95,
+            cache_hits: self.metrics.cache_hits.load(relaxed),
+            cache_misses: self.metrics.cache_misses.load(relaxed),
+            open_documents: self.documents.len(),
+        })
+    }
+
+    /// `aim/tryKeymap`: lookups against a keymap from the request body.
+    async fn lookup_request(
+        &self,
+        params: requests::LookupParams,
+    ) -> Result<requests::LookupResult> {
+        let prefix = params.prefix.strip_prefix('\\').unwrap_or(&params.prefix);
+        let keymap = self.keymap();
+        let symbols = if params.case_insensitive || self.settings.read().unwrap().case_insensitive
+        {
+            keymap.lookup_ci(prefix)
+        } else {
+            keymap.lookup(prefix)
+        };
+        Ok(requests::LookupResult {
+            symbols: symbols.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    async fn reverse_lookup(
+        &self,
+        params: requests::ReverseLookupParams,
+    ) -> Result<Vec<requests::ReverseLookupEntry>> {
+        Ok(params
+            .text
+            .chars()
+            .map(|c| requests::ReverseLookupEntry {
+                character: c,
+                sequences: self.reverse.lookup(&c.to_string()),
+            })
+            .collect())
+    }
+
+    /// (Re-)register completion with the current document selector, when
+    /// the client lets us do that dynamically. Runs after `initialized` and
+    /// again on every configuration change, so a new selector or trigger
+    /// set takes effect without a restart.
+    async fn register_completion(&self) {
+        if !self.supports_dynamic_completion() {
+            return;
+        }
+        let Some(selector) = self.settings.read().unwrap().document_selector.clone() else {
+            return;
+        };
+        let options = CompletionRegistrationOptions {
+            text_document_registration_options: TextDocumentRegistrationOptions {
+                document_selector: Some(selector),
+            },
+            completion_options: Self::completion_options(),
+        };
+        // drop any previous registration first; clients reject duplicate ids
+        let _ = self
+            .client
+            .unregister_capability(vec![Unregistration {
+                id: "aim.completion".to_string(),
+                method: "textDocument/completion".to_string(),
+            }])
+            .await;
+        let _ = self
+            .client
+            .register_capability(vec![Registration {
+                id: "aim.completion".to_string(),
+                method: "textDocument/completion".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            }])
+            .await;
+    }
+
+    fn status_snapshot(&self) -> requests::Status {
+        let files = {
+            let origins = self.keymap_origins.read().unwrap();
+            origins
+                .values()
+                .filter(|s| !matches!(s.as_str(), "embedded" | "families"))
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+        };
+        requests::Status {
+            profile: self.profile.read().unwrap().clone(),
+            entries: self.keymap().entries().len(),
+            files,
+            message: self.last_rebuild_error.read().unwrap().clone(),
+        }
+    }
+
+    async fn status(&self) -> Result<requests::Status> {
+        Ok(self.status_snapshot())
+    }
+
+    async fn dump_keymap(&self) -> Result<Vec<requests::KeymapEntry>> {
+        let origins = self.keymap_origins.read().unwrap().clone();
+        let mut entries = self.keymap().entries();
+        entries.sort();
+        entries.dedup();
+        Ok(entries
+            .into_iter()
+            .map(|(sequence, symbol)| {
+                let source = origins
+                    .get(&(sequence.clone(), symbol.clone()))
+                    .cloned()
+                    .unwrap_or_else(|| "startup".to_string());
+                requests::KeymapEntry {
+                    sequence,
+                    symbol,
+                    source,
+                }
+            })
+            .collect())
+    }
+
+    async fn try_keymap(
+        &self,
+        params: requests::TryKeymapParams,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let mut entries = 0;
+        Keymap::validate(&params.keymap, 0, &mut entries)
+            .map_err(tower_lsp::jsonrpc::Error::invalid_params)?;
+        let keymap = Keymap::new(params.keymap);
+        Ok(params
+            .prefixes
+            .into_iter()
+            .map(|p| {
+                let results = keymap.lookup(&p);
+                (p, results.iter().map(|s| s.to_string()).collect())
+            })
+            .collect())
+    }
+
+    /// `aim/browse`: paginated keymap listing for symbol palette UIs.
+    async fn browse(&self, params: requests::BrowseParams) -> Result<requests::BrowseResult> {
+        let filter = params.filter.unwrap_or_default();
+        let mut entries: Vec<requests::BrowseEntry> = self
+            .keymap()
+            .entries()
+            .into_iter()
+            .filter(|(seq, sym)| filter.is_empty() || seq.contains(&filter) || sym.contains(&filter))
+            .map(|(sequence, symbol)| {
+                let category = symbol
+                    .chars()
+                    .next()
+                    .map(unicode::block)
+                    .unwrap_or_default()
+                    .to_string();
+                requests::BrowseEntry {
+                    sequence,
+                    symbol,
+                    category,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            (&a.category, &a.sequence, &a.symbol).cmp(&(&b.category, &b.sequence, &b.symbol))
+        });
+
+        let total = entries.len();
+        let page_size = if params.page_size == 0 { 100 } else { params.page_size };
+        let start = (params.page * page_size).min(total);
+        let end = (start + page_size).min(total);
+        Ok(requests::BrowseResult {
+            entries: entries.drain(start..end).collect(),
+            total,
+            page: params.page,
+        })
+    }
+
+    async fn notebook_did_open(&self, params: notebook::DidOpenNotebookDocumentParams) {
+        tracing::info!(
+            "opened notebook {} (v{})",
+            params.notebook_document.uri,
+            params.notebook_document.version
+        );
+        for cell in params.cell_text_documents {
+            self.store_document(cell.uri, cell.text);
+        }
+    }
+
+    async fn notebook_did_change(&self, params: notebook::DidChangeNotebookDocumentParams) {
+        let Some(cells) = params.change.cells else {
+            return;
+        };
+        if let Some(structure) = cells.structure {
+            for opened in structure.did_open {
+                self.store_document(opened.uri, opened.text);
+            }
+            for closed in structure.did_close {
+                self.documents.remove(&closed.uri);
+                self.line_index.remove(&closed.uri);
+            }
+        }
+        for cell in cells.text_content {
+            if let Some(change) = cell.changes.into_iter().next_back() {
+                self.store_document(cell.document.uri, change.text);
+            }
+        }
+    }
+
+    async fn notebook_did_save(&self, params: notebook::DidSaveNotebookDocumentParams) {
+        tracing::info!("saved notebook {}", params.notebook_document.uri);
+    }
+
+    async fn notebook_did_close(&self, params: notebook::DidCloseNotebookDocumentParams) {
+        tracing::info!("closed notebook {}", params.notebook_document.uri);
+        for cell in params.cell_text_documents {
+            self.documents.remove(&cell.uri);
+            self.line_index.remove(&cell.uri);
+        }
+    }
+
+    /// Send one `$/progress` report under an already-created token.
+    async fn report_progress(&self, token: &NumberOrString, value: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+
+    /// Convert every file matching the configured globs under the workspace
+    /// roots, scanning concurrently and applying one edit per file.
+    async fn convert_workspace(&self) {
+        let globs = {
+            let settings = self.settings.read().unwrap();
+            convert::build_globset(&settings.convert_globs)
+        };
+        let Some(globs) = globs else { return };
+
+        let files: Vec<PathBuf> = self
+            .roots
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|root| convert::collect_files(root, &globs))
+            .collect();
+
+        let token = NumberOrString::String("aim.convertWorkspace".to_string());
+        let _ = self
+            .client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await;
+        self.report_progress(
+            &token,
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "converting workspace".to_string(),
+                percentage: Some(0),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        let keymap = self.keymap();
+        let mut tasks = tokio::task::JoinSet::new();
+        for path in files {
+            let keymap = keymap.clone();
+            tasks.spawn(async move {
+                let text = tokio::fs::read_to_string(&path).await.ok()?;
+                let replacements = convert::scan(&keymap, &text);
+                (!replacements.is_empty()).then_some((path, text, replacements))
+            });
+        }
+
+        let total = tasks.len();
+        let mut done = 0;
+        let mut converted = 0;
+        while let Some(res) = tasks.join_next().await {
+            done += 1;
+            self.report_progress(
+                &token,
+                WorkDoneProgress::Report(WorkDoneProgressReport {
+                    percentage: Some((done * 100 / total.max(1)) as u32),
+                    ..Default::default()
+                }),
+            )
+            .await;
+            if let Ok(Some((path, text, replacements))) = res
+                && let Ok(uri) = Url::from_file_path(&path)
+            {
+                let edit = convert::to_workspace_edit(uri, &text, &replacements, false, self.encoding());
+                // only edits the client actually applied count as converted
+                if self.client.apply_edit(edit).await.is_ok_and(|r| r.applied) {
+                    converted += 1;
+                }
+            }
+        }
+
+        self.report_progress(
+            &token,
+            WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(format!("converted {} of {} files", converted, total)),
+            }),
+        )
+        .await;
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        // take the client's first preference among the encodings we speak
+        let encoding = params
+            .capabilities
+            .general
+            .as_ref()
+            .and_then(|g| g.position_encodings.as_ref())
+            .and_then(|kinds| kinds.iter().find_map(text::Encoding::from_lsp))
+            .unwrap_or_default();
+        let _ = self.encoding.set(encoding);
+        let _ = self.capabilities.set(params.capabilities);
+        *self.init_options.write().unwrap() = params.initialization_options.clone();
+        *self.settings.write().unwrap() = config::Settings::new(params.initialization_options);
+        *self.profile.write().unwrap() = self.settings.read().unwrap().profile.clone();
+        *self.roots.write().unwrap() = params
+            .workspace_folders
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.uri.to_file_path().ok())
+            .collect();
+
+        self.rebuild_keymap().await;
+
+        // a broken startup keymap no longer stops the server from coming
+        // up; now that a client is attached, say what happened — the keymap
+        // can still arrive later via configuration or `aim.reloadKeymap`
+        if let Some(e) = &self.startup_error {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("aim: {}; using the built-in keymap until one is configured", e),
+                )
+                .await;
+        }
+
+        self.client
+            .log_message(MessageType::INFO, "aim server initialized!")
+            .await;
+
+        // with a configured selector and a dynamically-registering client,
+        // completion is registered in `initialized` instead
+        let register_dynamically = self.settings.read().unwrap().document_selector.is_some()
+            && self.supports_dynamic_completion();
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                position_encoding: Some(encoding.to_lsp()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        will_save_wait_until: Some(true),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
+                )),
+                completion_provider: (!register_dynamically).then(Self::completion_options),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: " ".to_string(),
+                    more_trigger_character: Some(vec!["\t".to_string()]),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        Self::semantic_tokens_options(),
+                    ),
+                ),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        "aim.convertDocument".to_string(),
+                        "aim.convertWorkspace".to_string(),
+                        "aim.expandAtCursor".to_string(),
+                        "aim.pick".to_string(),
+                        "aim.recordUsage".to_string(),
+                        "aim.addToKeymap".to_string(),
+                        "aim.insertSymbol".to_string(),
+                        "aim.setProfile".to_string(),
+                        "aim.reloadKeymap".to_string(),
+                        "aim.nextCandidate".to_string(),
+                        "aim.prevCandidate".to_string(),
+                    ],
+                    ..Default::default()
+                }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            "aim.convertDocument" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+                let dry_run = params
+                    .arguments
+                    .get(1)
+                    .and_then(|a| a.get("dryRun"))
+                    .and_then(|d| d.as_bool())
+                    .unwrap_or(false);
+                if let Some(uri) = uri
+                    && let Some(document) = self.documents.get(&uri).map(|d| d.clone())
+                {
+                    let replacements = self.scan_document(&document);
+                    if dry_run {
+                        let converted = convert::apply(&document, &replacements);
+                        return Ok(Some(serde_json::Value::String(convert::diff(
+                            &document, &converted,
+                        ))));
+                    }
+                    if !replacements.is_empty() {
+                        for r in &replacements {
+                            self.stats.record(&r.sequence);
+                        }
+                        let edit = convert::to_workspace_edit(
+                            uri,
+                            &document,
+                            &replacements,
+                            self.supports_change_annotations(),
+                            self.encoding(),
+                        );
+                        let _ = self.client.apply_edit(edit).await;
+                    }
+                }
+                Ok(None)
+            }
+            // switch the active keymap profile without a restart; an empty
+            // name goes back to the plain layering
+            "aim.setProfile" => {
+                let name = params
+                    .arguments
+                    .first()
+                    .and_then(|a| a.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                if !name.is_empty()
+                    && !self.settings.read().unwrap().profiles.contains_key(&name)
+                {
+                    self.client
+                        .show_message(
+                            MessageType::ERROR,
+                            format!("aim: no profile named `{}`", name),
+                        )
+                        .await;
+                    return Ok(None);
+                }
+                *self.profile.write().unwrap() = (!name.is_empty()).then_some(name);
+                // the rebuild pushes the status update itself
+                self.rebuild_keymap().await;
+                Ok(None)
+            }
+            // re-read every configured keymap source on demand, for clients
+            // that can't or don't want to set up file watching
+            "aim.reloadKeymap" => {
+                self.rebuild_keymap().await;
+                let status = self.status_snapshot();
+                let report = match &status.message {
+                    Some(problem) => (MessageType::WARNING, problem.clone()),
+                    None => (
+                        MessageType::INFO,
+                        format!("aim: reloaded {} entries", status.entries),
+                    ),
+                };
+                self.client.show_message(report.0, report.1).await;
+                Ok(serde_json::to_value(status).ok())
+            }
+            // insert a symbol (or a sequence's first expansion) at a
+            // position, for keybinding-driven workflows with no completion UI
+            "aim.insertSymbol" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+                let pos = params
+                    .arguments
+                    .get(1)
+                    .and_then(|a| serde_json::from_value::<Position>(a.clone()).ok());
+                let what = params.arguments.get(2).and_then(|a| a.as_str());
+                if let (Some(uri), Some(pos), Some(what)) = (uri, pos, what) {
+                    let symbol = match what.strip_prefix('\\') {
+                        Some(seq) => {
+                            let symbols = self.keymap().lookup(seq);
+                            let Some(first) = symbols.first() else {
+                                return Ok(None);
+                            };
+                            self.stats.record(seq);
+                            first.to_string()
+                        }
+                        None => what.to_string(),
+                    };
+                    let edit = TextEdit {
+                        range: Range { start: pos, end: pos },
+                        new_text: symbol,
+                    };
+                    let edit = WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri, vec![edit])])),
+                        ..Default::default()
+                    };
+                    let _ = self.client.apply_edit(edit).await;
+                }
+                Ok(None)
+            }
+            // append a new mapping to the user keymap file and hot-reload
+            // it; the symbol comes as the second argument, or from a prompt
+            // over the closest known candidates when the client sends none
+            "aim.addToKeymap" => {
+                let Some(seq) = params
+                    .arguments
+                    .first()
+                    .and_then(|a| a.as_str())
+                    .map(str::to_string)
+                else {
+                    return Ok(None);
+                };
+                let symbol = match params.arguments.get(1).and_then(|a| a.as_str()) {
+                    Some(sym) => Some(sym.to_string()),
+                    None => {
+                        let candidates: Vec<MessageActionItem> = self
+                            .fuzzy_index()
+                            .lookup(&seq)
+                            .into_iter()
+                            .take(8)
+                            .map(|s| MessageActionItem {
+                                title: s,
+                                properties: Default::default(),
+                            })
+                            .collect();
+                        if candidates.is_empty() {
+                            None
+                        } else {
+                            self.client
+                                .show_message_request(
+                                    MessageType::INFO,
+                                    format!("symbol for `\\{}`?", seq),
+                                    Some(candidates),
+                                )
+                                .await
+                                .ok()
+                                .flatten()
+                                .map(|a| a.title)
+                        }
+                    }
+                };
+                if let Some(symbol) = symbol {
+                    let path = self.keymap_sources().remove(0);
+                    match append_to_keymap(&path, &seq, &symbol) {
+                        Ok(()) => {
+                            self.rebuild_keymap().await;
+                            self.client
+                                .log_message(
+                                    MessageType::INFO,
+                                    format!(
+                                        "aim: added \\{} -> {} to {}",
+                                        seq,
+                                        symbol,
+                                        path.display()
+                                    ),
+                                )
+                                .await;
+                        }
+                        Err(e) => {
+                            self.client
+                                .show_message(
+                                    MessageType::ERROR,
+                                    format!("aim: cannot update {}: {}", path.display(), e),
+                                )
+                                .await;
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            // fired by the client after a completion item is inserted,
+            // feeding the frequency ranking
+            "aim.recordUsage" => {
+                if let Some(seq) = params.arguments.first().and_then(|a| a.as_str()) {
+                    self.stats.record(seq);
+                }
+                Ok(None)
+            }
+            "aim.convertWorkspace" => {
+                self.convert_workspace().await;
+                Ok(None)
+            }
+            // bindable directly to a key: expand the sequence ending at the
+            // cursor when it has exactly one candidate, skipping completion
+            "aim.expandAtCursor" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+                let pos = params
+                    .arguments
+                    .get(1)
+                    .and_then(|a| serde_json::from_value::<Position>(a.clone()).ok());
+                if let (Some(uri), Some(pos)) = (uri, pos)
+                    && let Some(document) = self.documents.get(&uri).map(|d| d.clone())
+                    && let Some(before) = text::before_cursor(&document, pos, self.encoding())
+                    && let Some((head, seq)) = before.rsplit_once(self.trigger())
+                    && !seq.is_empty()
+                    && let [symbol] = self.keymap().lookup(seq).as_slice()
+                {
+                    self.stats.record(seq);
+                    let replacement = convert::Replacement {
+                        line: pos.line,
+                        start: head.chars().count() as u32,
+                        end: before.chars().count() as u32,
+                        sequence: seq.to_string(),
+                        symbol: self.normalize(symbol),
+                    };
+                    self.remember_conversion(&uri, &replacement, vec![symbol.to_string()]);
+                    let edit = convert::to_workspace_edit(uri, &document, &[replacement], false, self.encoding());
+                    let _ = self.client.apply_edit(edit).await;
+                }
+                Ok(None)
+            }
+            // rotate the just-inserted symbol through the other candidates
+            // of its sequence, Agda-style (`\le` → ≤ → ⩽ → ≦)
+            "aim.nextCandidate" | "aim.prevCandidate" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+                let Some(uri) = uri else {
+                    return Ok(None);
+                };
+                let Some(last) = self.last_conversion.get(&uri).map(|l| l.clone()) else {
+                    return Ok(None);
+                };
+                if last.candidates.len() < 2 {
+                    return Ok(None);
+                }
+                let Some(document) = self.documents.get(&uri).map(|d| d.clone()) else {
+                    return Ok(None);
+                };
+                let step = if params.command == "aim.nextCandidate" {
+                    1
+                } else {
+                    last.candidates.len() - 1
+                };
+                let index = (last.index + step) % last.candidates.len();
+                let current = &last.candidates[last.index];
+                let replacement = convert::Replacement {
+                    line: last.line,
+                    start: last.start,
+                    end: last.start + current.chars().count() as u32,
+                    sequence: String::new(),
+                    symbol: last.candidates[index].clone(),
+                };
+                let edit = convert::to_workspace_edit(
+                    uri.clone(),
+                    &document,
+                    &[replacement],
+                    false,
+                    self.encoding(),
+                );
+                let _ = self.client.apply_edit(edit).await;
+                self.last_conversion
+                    .insert(uri, LastConversion { index, ..last });
+                Ok(None)
+            }
+            // lowest-common-denominator insertion path for clients without a
+            // usable completion UI: page candidates through
+            // `window/showMessageRequest` and apply the chosen one
+            "aim.pick" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+                let pos = params
+                    .arguments
+                    .get(1)
+                    .and_then(|a| serde_json::from_value::<Position>(a.clone()).ok());
+                let (Some(uri), Some(pos)) = (uri, pos) else {
+                    return Ok(None);
+                };
+                let document = self
+                    .documents
+                    .get(&uri)
+                    .map(|d| d.clone())
+                    .unwrap_or_default();
+                let prefix = params
+                    .arguments
+                    .get(2)
+                    .and_then(|a| a.as_str())
+                    .map(str::to_string)
+                    .or_else(|| {
+                        let before = text::before_cursor(&document, pos, self.encoding())?;
+                        before
+                            .rsplit_once(self.trigger())
+                            .map(|(_, seq)| seq.to_string())
+                    });
+                let Some(prefix) = prefix else {
+                    return Ok(None);
+                };
+                let mut matches: Vec<(String, String)> = self
+                    .keymap()
+                    .entries()
+                    .into_iter()
+                    .filter(|(seq, _)| seq.starts_with(&prefix))
+                    .collect();
+                matches.sort();
+                matches.dedup();
+
+                const PAGE: usize = 8;
+                let mut page = 0;
+                let chosen = loop {
+                    let slice =
+                        &matches[(page * PAGE).min(matches.len())..((page + 1) * PAGE).min(matches.len())];
+                    if slice.is_empty() {
+                        break None;
+                    }
+                    let title = |(seq, sym): &(String, String)| format!("{}  \\{}", sym, seq);
+                    let mut actions: Vec<MessageActionItem> = slice
+                        .iter()
+                        .map(|entry| MessageActionItem {
+                            title: title(entry),
+                            properties: Default::default(),
+                        })
+                        .collect();
+                    if (page + 1) * PAGE < matches.len() {
+                        actions.push(MessageActionItem {
+                            title: "more…".to_string(),
+                            properties: Default::default(),
+                        });
+                    }
+                    let picked = self
+                        .client
+                        .show_message_request(
+                            MessageType::INFO,
+                            format!("candidates for \\{}", prefix),
+                            Some(actions),
+                        )
+                        .await
+                        .ok()
+                        .flatten();
+                    match picked {
+                        Some(item) if item.title == "more…" => page += 1,
+                        Some(item) => {
+                            break slice.iter().find(|entry| title(entry) == item.title).cloned();
+                        }
+                        None => break None,
+                    }
+                };
+                if let Some((seq, sym)) = chosen {
+                    self.stats.record(&seq);
+                    let cursor = text::before_cursor(&document, pos, self.encoding())
+                        .map(|b| b.chars().count())
+                        .unwrap_or(pos.character as usize);
+                    let candidates = matches
+                        .iter()
+                        .filter(|(s, _)| *s == seq)
+                        .map(|(_, sym)| sym.clone())
+                        .collect();
+                    let replacement = convert::Replacement {
+                        line: pos.line,
+                        start: cursor.saturating_sub(prefix.chars().count() + 1) as u32,
+                        end: cursor as u32,
+                        sequence: seq,
+                        symbol: self.normalize(&sym),
+                    };
+                    self.remember_conversion(&uri, &replacement, candidates);
+                    let edit = convert::to_workspace_edit(uri, &document, &[replacement], false, self.encoding());
+                    let _ = self.client.apply_edit(edit).await;
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.register_completion().await;
+
+        // watch the keymap source files so edits reload without a restart
+        let watches = self
+            .capabilities
+            .get()
+            .and_then(|c| c.workspace.as_ref())
+            .and_then(|w| w.did_change_watched_files.as_ref())
+            .and_then(|w| w.dynamic_registration)
+            .unwrap_or(false);
+        if watches {
+            let watchers: Vec<FileSystemWatcher> = self
+                .keymap_sources()
+                .into_iter()
+                .map(|p| FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(p.display().to_string()),
+                    kind: None,
+                })
+                .collect();
+            let _ = self
+                .client
+                .register_capability(vec![Registration {
+                    id: "aim.keymapWatch".to_string(),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                    register_options: serde_json::to_value(
+                        DidChangeWatchedFilesRegistrationOptions { watchers },
+                    )
+                    .ok(),
+                }])
+                .await;
+        }
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // re-resolve the full layer stack with the pushed settings as the
+        // topmost overlay, then rebuild everything derived from it
+        let init = self.init_options.read().unwrap().clone();
+        let workspace = (!params.settings.is_null()).then_some(params.settings);
+        *self.settings.write().unwrap() = config::Settings::resolve(init, workspace);
+        *self.profile.write().unwrap() = self.settings.read().unwrap().profile.clone();
+        self.rebuild_keymap().await;
+        self.register_completion().await;
+        tracing::info!("configuration reloaded");
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if params.changes.is_empty() {
+            return;
+        }
+        self.rebuild_keymap().await;
+        tracing::info!("keymap reloaded after a watched-file change");
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        {
+            let mut roots = self.roots.write().unwrap();
+            let removed: Vec<PathBuf> = params
+                .event
+                .removed
+                .iter()
+                .filter_map(|f| f.uri.to_file_path().ok())
+                .collect();
+            roots.retain(|r| !removed.contains(r));
+            for root in &removed {
+                self.folder_keymaps.remove(root);
+            }
+            roots.extend(
+                params
+                    .event
+                    .added
+                    .iter()
+                    .filter_map(|f| f.uri.to_file_path().ok()),
+            );
+        }
+        // the single-root global merge may have become a per-folder overlay
+        // (or the other way round), so the whole stack is recomputed
+        self.rebuild_keymap().await;
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        self.languages.insert(
+            params.text_document.uri.clone(),
+            params.text_document.language_id,
+        );
+        self.store_document(params.text_document.uri.clone(), params.text_document.text);
+        self.versions
+            .insert(params.text_document.uri.clone(), params.text_document.version);
+        self.schedule_diagnostics(params.text_document.uri);
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let version = params.text_document.version;
+        if self.versions.get(&uri).is_some_and(|v| *v >= version) {
+            // out-of-order delivery; applying this would corrupt the document
+            return;
+        }
+        self.versions.insert(uri.clone(), version);
+        let mut document = self.documents.get(&uri).map(|d| d.clone()).unwrap_or_default();
+        for change in &params.content_changes {
+            document = text::apply_change(&document, change.range, &change.text, self.encoding());
+        }
+        self.store_document(uri.clone(), document.clone());
+        // opt-in auto-expansion: a terminator typed right after a complete,
+        // unambiguous sequence converts it on the spot, Agda-style
+        if self.settings.read().unwrap().auto_expand
+            && self.enabled_for(&uri)
+            && let Some(change) = params.content_changes.last()
+            && let Some(range) = change.range
+            && change.text.chars().count() == 1
+            && !change.text.starts_with(self.trigger())
+            && change
+                .text
+                .chars()
+                .all(|c| c.is_whitespace() || c.is_ascii_punctuation())
+            && let Some(before) = text::before_cursor(&document, range.start, self.encoding())
+            && let Some((head, seq)) = before.rsplit_once(self.trigger())
+            && !seq.is_empty()
+            && !seq.contains(char::is_whitespace)
+            && let [symbol] = self.keymap().lookup(seq).as_slice()
+        {
+            self.stats.record(seq);
+            let replacement = convert::Replacement {
+                line: range.start.line,
+                start: head.chars().count() as u32,
+                end: before.chars().count() as u32,
+                sequence: seq.to_string(),
+                symbol: self.normalize(symbol),
+            };
+            self.remember_conversion(&uri, &replacement, vec![symbol.to_string()]);
+            let edit = convert::to_workspace_edit(
+                uri.clone(),
+                &document,
+                &[replacement],
+                false,
+                self.encoding(),
+            );
+            let _ = self.client.apply_edit(edit).await;
+        }
+        // `:tada:` style: typing an extra trigger right after a complete
+        // sequence of its own trie closes it and expands on the spot,
+        // swallowing both delimiters
+        let closed = params.content_changes.last().and_then(|change| {
+            let range = change.range?;
+            let mut typed = change.text.chars();
+            let c = typed.next().filter(|_| typed.next().is_none())?;
+            let bound = self.trigger_keymaps.read().unwrap().get(&c).cloned()?;
+            let before = text::before_cursor(&document, range.start, self.encoding())?;
+            let (head, seq) = before.rsplit_once(c)?;
+            if seq.is_empty() || seq.contains(char::is_whitespace) {
+                return None;
+            }
+            let (len, symbols) = bound.longest_match(seq)?;
+            (len == seq.chars().count()).then(|| {
+                (
+                    convert::Replacement {
+                        line: range.start.line,
+                        start: head.chars().count() as u32,
+                        // one past the cursor, to swallow the closing delimiter
+                        end: before.chars().count() as u32 + 1,
+                        sequence: seq.to_string(),
+                        symbol: self.normalize(&symbols[0]),
+                    },
+                    symbols.iter().map(|s| s.to_string()).collect(),
+                )
+            })
+        });
+        if self.enabled_for(&uri)
+            && let Some((replacement, candidates)) = closed
+        {
+            self.stats.record(&replacement.sequence);
+            self.remember_conversion(&uri, &replacement, candidates);
+            let edit = convert::to_workspace_edit(
+                uri.clone(),
+                &document,
+                &[replacement],
+                false,
+                self.encoding(),
+            );
+            let _ = self.client.apply_edit(edit).await;
+        }
+        self.schedule_diagnostics(uri);
+    }
+
+    async fn will_save_wait_until(
+        &self,
+        params: WillSaveTextDocumentParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        if !self.expands_on_save(&uri) {
+            return Ok(None);
+        }
+        Ok(self.documents.get(&uri).map(|d| {
+            self.scan_document(&d)
+                .iter()
+                .inspect(|r| self.stats.record(&r.sequence))
+                .map(|r| convert::to_text_edit(d.lines().nth(r.line as usize).unwrap_or(""), r, self.encoding()))
+                .collect()
+        }))
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        // fallback for clients that save without sending willSaveWaitUntil
+        let uri = params.text_document.uri;
+        if !self.expands_on_save(&uri) {
+            return;
+        }
+        let Some(document) = self.documents.get(&uri).map(|d| d.clone()) else {
+            return;
+        };
+        let replacements = self.scan_document(&document);
+        if !replacements.is_empty() {
+            let edit = convert::to_workspace_edit(uri, &document, &replacements, false, self.encoding());
+            let _ = self.client.apply_edit(edit).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.remove(&uri);
+        self.line_index.remove(&uri);
+        self.versions.remove(&uri);
+        self.languages.remove(&uri);
+        self.diag_revision.remove(&uri);
+        if self.published_diags.remove(&uri).is_some() {
+            self.client.publish_diagnostics(uri, vec![], None).await;
+        }
+    }
+
+    /// "How do I type this?": hovering a character the keymap can produce
+    /// lists every sequence that expands to it.
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let pos = params.text_document_position_params.position;
+        let Some(line) = self
+            .documents
+            .get(&uri)
+            .and_then(|d| d.lines().nth(pos.line as usize).map(|l| l.to_string()))
+        else {
+            return Ok(None);
+        };
+        // an unexpanded `\sequence` under the cursor: list what it could
+        // become, without opening the completion menu on it
+        let chars: Vec<char> = line.chars().collect();
+        let at = text::char_index(&line, pos.character as usize, self.encoding());
+        let trigger = self.trigger();
+        if let Some(bs) = chars[..at.min(chars.len())].iter().rposition(|&c| c == trigger) {
+            let end = chars[bs + 1..]
+                .iter()
+                .position(|c| c.is_whitespace() || *c == trigger)
+                .map(|n| bs + 1 + n)
+                .unwrap_or(chars.len());
+            let seq: String = chars[bs + 1..end].iter().collect();
+            if at <= end && !seq.is_empty() {
+                let symbols = self.keymap().lookup(&seq);
+                if !symbols.is_empty() {
+                    let kind = self.hover_markup();
+                    let value = format!("`\\{}` → {}", seq, symbols.join(" "));
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            value: if kind == MarkupKind::PlainText {
+                                value.replace('`', "")
+                            } else {
+                                value
+                            },
+                            kind,
+                        }),
+                        range: None,
+                    }));
+                }
+            }
+        }
+        let Some(c) = text::char_at(&line, pos.character as usize, self.encoding()) else {
+            return Ok(None);
+        };
+        let seqs = self.reverse.lookup(&c.to_string());
+        if seqs.is_empty() {
+            return Ok(None);
+        }
+        let list = seqs
+            .iter()
+            .map(|s| format!("`\\{}`", s))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let kind = self.hover_markup();
+        let value = format!("{}\n\ntype with {}", unicode::describe(&c.to_string()), list);
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                value: if kind == MarkupKind::PlainText {
+                    value.replace('`', "")
+                } else {
+                    value
+                },
+                kind,
+            }),
+            range: None,
+        }))
+    }
+
+    /// Preview what each complete `\sequence` in the requested range would
+    /// expand to, inline after the sequence.
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        let Some(text) = self.documents.get(&uri).map(|d| d.clone()) else {
+            return Ok(None);
+        };
+        let enc = self.encoding();
+        let hints = convert::scan_with(&self.keymap(), &text, self.trigger())
+            .into_iter()
+            .filter(|r| params.range.start.line <= r.line && r.line <= params.range.end.line)
+            .map(|r| {
+                let line = text.lines().nth(r.line as usize).unwrap_or("");
+                InlayHint {
+                    position: Position {
+                        line: r.line,
+                        character: text::col(line, r.end as usize, enc),
+                    },
+                    label: InlayHintLabel::String(r.symbol.clone()),
+                    kind: None,
+                    text_edits: None,
+                    tooltip: Some(InlayHintTooltip::String(format!("\\{}", r.sequence))),
+                    padding_left: Some(true),
+                    padding_right: None,
+                    data: None,
+                }
+            })
+            .collect();
+        Ok(Some(hints))
+    }
+
+    /// The standards-based sibling of auto-expansion: typing a space or tab
+    /// after a complete, unambiguous sequence returns a formatting edit
+    /// replacing it, for clients that dislike server-initiated `applyEdit`.
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let Some(document) = self.documents.get(&uri).map(|d| d.clone()) else {
+            return Ok(None);
+        };
+        let enc = self.encoding();
+        let Some(before) = text::before_cursor(&document, pos, enc) else {
+            return Ok(None);
+        };
+        // the position is after the typed trigger; drop it from the prefix
+        let before = before.strip_suffix(&params.ch).unwrap_or(before);
+        let Some((head, seq)) = before.rsplit_once(self.trigger()) else {
+            return Ok(None);
+        };
+        if seq.is_empty() || seq.contains(char::is_whitespace) {
+            return Ok(None);
+        }
+        let symbols = self.keymap().lookup(seq);
+        let [symbol] = symbols.as_slice() else {
+            return Ok(None);
+        };
+        self.stats.record(seq);
+        let r = convert::Replacement {
+            line: pos.line,
+            start: head.chars().count() as u32,
+            end: before.chars().count() as u32,
+            sequence: seq.to_string(),
+            symbol: symbol.to_string(),
+        };
+        let line = document.lines().nth(pos.line as usize).unwrap_or("");
+        Ok(Some(vec![convert::to_text_edit(line, &r, enc)]))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let uri = params.text_document.uri;
+        let Some(text) = self.documents.get(&uri).map(|d| d.clone()) else {
+            return Ok(None);
+        };
+        let mut data = vec![];
+        let (mut prev_line, mut prev_start) = (0, 0);
+        for r in convert::scan_with(&self.keymap(), &text, self.trigger()) {
+            let delta_line = r.line - prev_line;
+            data.push(SemanticToken {
+                delta_line,
+                delta_start: if delta_line == 0 {
+                    r.start - prev_start
+                } else {
+                    r.start
+                },
+                length: r.end - r.start,
+                token_type: 0,
+                token_modifiers_bitset: 0,
+            });
+            (prev_line, prev_start) = (r.line, r.start);
+        }
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data,
+        })))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let pos = params.range.start;
+        let Some(document) = self.documents.get(&uri).map(|d| d.clone()) else {
+            return Ok(None);
+        };
+        let Some(line) = document.lines().nth(pos.line as usize).map(|l| l.to_string()) else {
+            return Ok(None);
+        };
+        let chars: Vec<char> = line.chars().collect();
+
+        let mut actions = vec![];
+
+        // replace the escape sequence under the cursor with its symbol
+        let at = text::char_index(&line, pos.character as usize, self.encoding()) as u32;
+        for r in convert::scan_with(&self.keymap(), &line, self.trigger()) {
+            if r.start <= at && at <= r.end {
+                let mut r = r;
+                r.line = pos.line;
+                let edit = convert::to_text_edit(&line, &r, self.encoding());
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("replace `\\{}` with `{}`", r.sequence, r.symbol),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        // the whole file in one go, the code-action face of the
+        // `aim.convertDocument` command
+        let replacements = self.scan_document(&document);
+        if !replacements.is_empty() {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!(
+                    "convert all {} escape sequences in this file",
+                    replacements.len()
+                ),
+                kind: Some(CodeActionKind::SOURCE),
+                edit: Some(convert::to_workspace_edit(
+                    uri.clone(),
+                    &document,
+                    &replacements,
+                    false,
+                    self.encoding(),
+                )),
+                ..Default::default()
+            }));
+        }
+
+        // turn a symbol back into its escape sequence, for files that have
+        // to stay ASCII-only
+        if let Some(&c) = chars.get(at as usize)
+            && let Some(seq) = self.reverse.lookup(&c.to_string()).first()
+        {
+            let edit = TextEdit {
+                range: Range {
+                    start: Position {
+                        line: pos.line,
+                        character: text::col(&line, at as usize, self.encoding()),
+                    },
+                    end: Position {
+                        line: pos.line,
+                        character: text::col(&line, at as usize + 1, self.encoding()),
+                    },
+                },
+                new_text: format!("\\{}", seq),
+            };
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("replace `{}` with `\\{}`", c, seq),
+                kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+
+        // adopt a sequence the lints flagged as unknown
+        for d in &params.context.diagnostics {
+            if d.code == Some(NumberOrString::String("unknown-sequence".to_string())) {
+                let s = text::char_index(&line, d.range.start.character as usize, self.encoding());
+                let e = text::char_index(&line, d.range.end.character as usize, self.encoding());
+                let token: String =
+                    chars[s.min(chars.len())..e.min(chars.len())].iter().collect();
+                if let Some(seq) = token.strip_prefix('\\') {
+                    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: format!("add `\\{}` to the user keymap", seq),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![d.clone()]),
+                        command: Some(Command {
+                            title: "add to keymap".to_string(),
+                            command: "aim.addToKeymap".to_string(),
+                            arguments: Some(vec![serde_json::json!(seq)]),
+                        }),
+                        ..Default::default()
+                    }));
+                }
+            }
+        }
+
+        // annotate a known symbol with its input sequence in a comment
+        if let Some(&c) = chars.get(pos.character as usize) {
+            let seqs = self.reverse.lookup(&c.to_string());
+            if let Some(seq) = seqs.first() {
+                let lang = self.languages.get(&uri).map(|l| l.clone()).unwrap_or_default();
+                let (open, close) = comment_syntax(&lang);
+                let edit = TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: pos.line,
+                            character: chars.len() as u32,
+                        },
+                        end: Position {
+                            line: pos.line,
+                            character: chars.len() as u32,
+                        },
+                    },
+                    new_text: format!("  {} {} = \\{}{}", open, c, seq, close),
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("annotate {} with its input sequence", c),
+                    kind: Some(CodeActionKind::EMPTY),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        Ok((!actions.is_empty()).then_some(actions))
+    }
+
+    /// Informational symbol search: querying e.g. "forall" in the editor's
+    /// symbol picker surfaces the matching Unicode characters.
+    #[allow(deprecated)]
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let query = params.query.to_lowercase();
+        if query.is_empty() {
+            return Ok(None);
+        }
+        let location = Location {
+            uri: Url::parse("aim:symbols").unwrap(),
+            range: Range::default(),
+        };
+        let symbols: Vec<SymbolInformation> = self
+            .keymap()
+            .entries()
+            .into_iter()
+            .filter(|(seq, _)| seq.to_lowercase().contains(&query))
+            .take(100)
+            .map(|(seq, sym)| SymbolInformation {
+                name: format!("{} \\{}", sym, seq),
+                kind: SymbolKind::STRING,
+                tags: None,
+                deprecated: None,
+                location: location.clone(),
+                container_name: None,
+            })
+            .collect();
+        Ok((!symbols.is_empty()).then_some(symbols))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let started = std::time::Instant::now();
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        if !self.enabled_for(&uri) {
+            return Ok(None);
+        }
+
+        let document = self.documents.get(&uri);
+
+        let line = document
+            .as_ref()
+            .and_then(|d| self.current_line(&uri, d.value(), position.line))
+            .map(|l| text::prefix_of(l, position.character, self.encoding()).to_string());
+
+        // a cursor past the end of the stored line means the request was made
+        // against a newer document state than we have; don't answer from it
+        if let Some(l) = &line
+            && (position.character as usize) > text::width(l, self.encoding())
+        {
+            tracing::debug!("completion: stale document state for {}", uri);
+            return Ok(None);
+        }
+
+        // opt-in for LaTeX/Markdown: outside math context, a `\alpha` is
+        // prose and gets no completion
+        if self.settings.read().unwrap().math_mode_only
+            && let Some(d) = &document
+            && !context::in_math(
+                d.value(),
+                text::byte_offset(d.value(), position, self.encoding()),
+            )
+        {
+            tracing::debug!("completion: outside math mode");
+            return Ok(None);
+        }
+
+        // user-defined context gates: e.g. only inside `--.*` line
+        // comments, or never inside string literals
+        let (include, exclude) = {
+            let settings = self.settings.read().unwrap();
+            (
+                settings.context_include.clone(),
+                settings.context_exclude.clone(),
+            )
+        };
+        if (!include.is_empty() || !exclude.is_empty())
+            && let Some(d) = &document
+            && let Some(full) = self.current_line(&uri, d.value(), position.line)
+            && !context::line_allowed(full, &include, &exclude)
+        {
+            tracing::debug!("completion: context filters reject the line");
+            return Ok(None);
+        }
+
+        let prefix = line.as_ref().and_then(|l| self.nearest_trigger(l));
+
+        if let Some((trigger, prefix, bound)) = prefix {
+            // a bare trigger offers nothing unless the opt-in browse mode
+            // is on, in which case the empty prefix flows through the
+            // normal pipeline: the breadth-first flatten puts the shortest
+            // sequences first, the usage boost lifts familiar ones over
+            // them, and the result cap keeps the list scrollable
+            if prefix.is_empty() && !self.settings.read().unwrap().browse_on_bare_trigger {
+                return Ok(None);
+            }
+            // the rest of the token right of the cursor; the edit replaces
+            // it too, so accepting in the middle of `\alp|ha` doesn't leave
+            // `ha` dangling after the inserted symbol
+            let tail: String = document
+                .as_ref()
+                .and_then(|d| self.current_line(&uri, d.value(), position.line))
+                .zip(line.as_deref())
+                .map(|(full, before)| {
+                    full[before.len()..]
+                        .chars()
+                        .take_while(|c| !c.is_whitespace() && *c != trigger)
+                        .collect()
+                })
+                .unwrap_or_default();
+            // tower-lsp answers `$/cancelRequest` by dropping the request's
+            // future, but that can only happen at an await point; yield
+            // before the lookup stages so the request a keystroke just
+            // superseded stops doing work instead of running to completion
+            tokio::task::yield_now().await;
+            let case_insensitive = self.settings.read().unwrap().case_insensitive;
+            let per_language = self.keymap_for(&uri);
+            let per_folder = self.folder_keymap_for(&uri);
+            let rel = self.relative_path(&uri);
+            let active = self.keymap();
+            let lookup = |p: &str| {
+                let ret = match (&per_language, &self.compiled) {
+                    (Some(keymap), _) if case_insensitive => keymap.lookup_ci(p),
+                    (Some(keymap), _) => keymap.lookup_at(p, &rel),
+                    (None, Some(compiled)) => {
+                        compiled.lookup(p).into_iter().map(Arc::from).collect()
+                    }
+                    (None, None) if case_insensitive => active.lookup_ci(p),
+                    (None, None) => {
+                        // the arena answers the common ungated part;
+                        // document-gated symbols still need the nested walk
+                        let mut ret = self.flat_trie().lookup(p);
+                        active.gated_at(p, &rel, &mut ret);
+                        ret
+                    }
+                };
+                // the owning folder's overrides go first, without repeats
+                match &per_folder {
+                    Some(folder) => {
+                        let mut overlay = if case_insensitive {
+                            folder.lookup_ci(p)
+                        } else {
+                            folder.lookup(p)
+                        };
+                        let tail: Vec<_> = ret
+                            .into_iter()
+                            .filter(|s| !overlay.contains(s))
+                            .collect();
+                        overlay.extend(tail);
+                        overlay
+                    }
+                    None => ret,
+                }
+            };
+            // CJK modes return dozens of candidates per syllable; those get
+            // numbered labels so users can pick by eye the way they would
+            // in a real input method
+            let mut numbered = false;
+            let mut candidates = if let Some(bound) = &bound {
+                // an extra trigger dispatches straight to its own trie
+                if case_insensitive {
+                    bound.lookup_ci(prefix)
+                } else {
+                    bound.lookup(prefix)
+                }
+            } else {
+                match self
+                    .pinyin_candidates(prefix)
+                    .or_else(|| self.zhuyin_candidates(prefix))
+                    .or_else(|| self.cangjie_candidates(prefix))
+                    .or_else(|| self.kana_candidates(prefix))
+                    .or_else(|| self.hangul_candidates(prefix))
+                {
+                    Some(cjk) => {
+                        numbered = true;
+                        cjk.into_iter().map(Arc::from).collect()
+                    }
+                    None => lookup(prefix),
+                }
+            };
+            // fall through the configured keymap chain when the active map
+            // has no match, remembering which map answered; extra triggers
+            // Greek transliteration composes with the trie: rules only
+            // answer for sequences no keymap entry covers
+            if candidates.is_empty()
+                && bound.is_none()
+                && let Some(greek) = self.greek_candidates(prefix)
+            {
+                candidates = greek.into_iter().map(Arc::from).collect();
+            }
+            // stay within their own trie
+            let mut fallback_source: Option<String> = None;
+            if candidates.is_empty() && bound.is_none() {
+                let chain = self.settings.read().unwrap().fallback_keymaps.clone();
+                for path in &chain {
+                    let keymap = self.load_keymap_file(path);
+                    candidates = if case_insensitive {
+                        keymap.lookup_ci(prefix)
+                    } else {
+                        keymap.lookup(prefix)
+                    };
+                    if !candidates.is_empty() {
+                        fallback_source = Some(path.display().to_string());
+                        break;
+                    }
+                }
+                if candidates.is_empty() && per_language.is_some() {
+                    candidates = active.lookup(prefix);
+                    if !candidates.is_empty() {
+                        fallback_source = Some("global".to_string());
+                    }
+                }
+            }
+            // tolerate one adjacent-key typo when nothing matches
+            if candidates.is_empty()
+                && bound.is_none()
+                && let Some(rows) = self.settings.read().unwrap().keyboard_layout.clone()
+            {
+                let rows: Vec<&str> = rows.iter().map(|r| r.as_str()).collect();
+                candidates = fuzzy::Adjacency::new(&rows)
+                    .variants(prefix)
+                    .iter()
+                    .flat_map(|v| lookup(v))
+                    .collect();
+                candidates.dedup();
+            }
+            // optional fuzzy mode: substring/subsequence sequence matches
+            // and Unicode-name matches once everything strict came up empty;
+            // this is the slow path over huge keymaps, so give a cancelled
+            // request its last chance to bail out first
+            tokio::task::yield_now().await;
+            if candidates.is_empty() && bound.is_none() && self.settings.read().unwrap().fuzzy_matching {
+                candidates = self
+                    .fuzzy_index()
+                    .lookup(prefix)
+                    .into_iter()
+                    .map(Arc::from)
+                    .collect();
+            }
+            // last resort: search official Unicode character names, so any
+            // character is reachable without a keymap entry (`\black-star`
+            // → ★); query words are hyphen-separated, since a space would
+            // have ended the token
+            if candidates.is_empty() && bound.is_none() && prefix.chars().count() >= 3 {
+                let (enabled, cap) = {
+                    let settings = self.settings.read().unwrap();
+                    (settings.unicode_name_search, settings.max_candidates.max(1))
+                };
+                if enabled {
+                    candidates = unicode::search_names(prefix, cap)
+                        .into_iter()
+                        .map(|c| Arc::from(c.to_string()))
+                        .collect();
+                    if !candidates.is_empty() {
+                        fallback_source = Some("unicode names".to_string());
+                    }
+                }
+            }
+            // hide candidates outside the blocks the editor's font covers
+            let allowed = self.settings.read().unwrap().allowed_blocks.clone();
+            if !allowed.is_empty() {
+                candidates.retain(|s| {
+                    s.chars()
+                        .all(|c| allowed.iter().any(|b| b == unicode::block(c)))
+                });
+            }
+            // structured ranking: the exact sequence's own symbols first,
+            // then descendants with shorter remaining sequences — sources
+            // like the compiled cache hand back lexicographic order, which
+            // would bury `λ` under obscure long sequences otherwise
+            let ranked: HashMap<Arc<str>, (String, usize)> = if bound.is_none() && !numbered {
+                let mut ranked = HashMap::new();
+                for c in active.lookup_ranked(prefix) {
+                    ranked.entry(c.symbol).or_insert((c.sequence, c.depth));
+                }
+                candidates
+                    .sort_by_key(|s| ranked.get(s).map_or(usize::MAX, |(_, d)| *d));
+                ranked
+            } else {
+                HashMap::new()
+            };
+            // boost what the user actually inserts; the sort is stable, so
+            // the deterministic order still decides between equal counts
+            candidates.sort_by_key(|s| {
+                std::cmp::Reverse(
+                    self.reverse
+                        .lookup(s)
+                        .into_iter()
+                        .find(|q| q.starts_with(prefix))
+                        .map(|q| self.stats.count(&q))
+                        .unwrap_or(0),
+                )
+            });
+            let (label_template, detail_template, max_candidates, output_flavor, plain_insert) = {
+                let settings = self.settings.read().unwrap();
+                (
+                    settings.label_template.clone(),
+                    settings.detail_template.clone(),
+                    settings.max_candidates.max(1),
+                    settings.output_flavor.clone(),
+                    settings.insert_text_fallback,
+                )
+            };
+            let plain_insert = plain_insert || !self.supports_text_edit();
+            // a template the user customized (or a named preset) controls
+            // the whole label; the labelDetails presentation only applies
+            // to the default, since e.g. the screen-reader preset is
+            // pointless with the glyph split off into the details
+            let label_template = resolve_label_template(&label_template).to_string();
+            let custom_label = label_template != config::Settings::default().label_template;
+            // the trigger is configurable, so its column span is not 1 for
+            // a non-ASCII trigger under utf-8 (or a non-BMP one under utf-16)
+            let trigger_width = text::width(&trigger.to_string(), self.encoding()) as u32;
+            let overflow = candidates.len().saturating_sub(max_candidates);
+            candidates.truncate(max_candidates);
+            let mut completion_items: Vec<CompletionItem> = candidates
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    // the full sequence the user would type for this symbol,
+                    // so client-side filtering keeps matching as they type
+                    // on; the ranked lookup knows it directly, the reverse
+                    // index covers symbols from the other sources
+                    let sequence = ranked
+                        .get(&s)
+                        .map(|(q, _)| q.clone())
+                        .or_else(|| {
+                            self.reverse
+                                .lookup(&s)
+                                .into_iter()
+                                .find(|q| q.starts_with(prefix))
+                        })
+                        .unwrap_or_else(|| prefix.to_string());
+                    // with labelDetails support the sequence is the label and
+                    // the glyph sits beside it; otherwise keep the template
+                    let (label, label_details) = if numbered {
+                        // stable 1-based numbering, the way an input method
+                        // presents a candidate row
+                        (format!("{} {}", i + 1, s), None)
+                    } else if self.supports_label_details() && !custom_label {
+                        (
+                            format!("{}{}", trigger, sequence),
+                            Some(CompletionItemLabelDetails {
+                                detail: None,
+                                description: Some(display_symbol(&s)),
+                            }),
+                        )
+                    } else {
+                        (render_template(&label_template, prefix, &s), None)
+                    };
+                    // snippet entries insert with their tabstops when the
+                    // client can place the cursor, and stripped otherwise
+                    let snippet = is_snippet(&s) && self.supports_snippets();
+                    let inserted = if is_snippet(&s) && !snippet {
+                        strip_tabstops(&s)
+                    } else {
+                        s.to_string()
+                    };
+                    // a configured non-unicode flavor inserts the entry's
+                    // alternate spelling when it declares one
+                    let inserted = if output_flavor != "unicode" {
+                        self.flavors
+                            .read()
+                            .unwrap()
+                            .get(s.as_ref())
+                            .and_then(|m| m.get(&output_flavor))
+                            .cloned()
+                            .unwrap_or(inserted)
+                    } else {
+                        inserted
+                    };
+                    // a combining symbol attaches to the character before
+                    // the trigger: the edit swallows that base character and
+                    // re-inserts it with the mark on top (`x\hat` → `x̂`)
+                    let base = s
+                        .chars()
+                        .next()
+                        .filter(|c| unicode::is_combining(*c))
+                        .and_then(|_| {
+                            let l = line.as_deref()?;
+                            l[..l.len() - prefix.len() - trigger.len_utf8()]
+                                .chars()
+                                .next_back()
+                                .filter(|b| !b.is_whitespace())
+                        });
+                    let new_text = self.normalize(&match base {
+                        Some(b) => format!("{}{}", b, inserted),
+                        None => inserted.clone(),
+                    });
+                    // minimal clients get the symbol as plain insertText
+                    // and do their own word replacement; everyone else gets
+                    // the precise edit over `\prefix` (and the base char)
+                    let (text_edit, insert_text) = if !plain_insert {
+                        let edit = TextEdit {
+                            range: Range {
+                                start: Position {
+                                    line: position.line,
+                                    character: position.character
+                                        - text::width(prefix, self.encoding()) as u32
+                                        - trigger_width
+                                        - base.map_or(0, |b| {
+                                            text::width(&b.to_string(), self.encoding()) as u32
+                                        }),
+                                },
+                                end: Position {
+                                    line: position.line,
+                                    character: position.character
+                                        + text::width(&tail, self.encoding()) as u32,
+                                },
+                            },
+                            new_text,
+                        };
+                        (Some(CompletionTextEdit::Edit(edit)), None)
+                    } else {
+                        (None, Some(new_text))
+                    };
+                    CompletionItem {
+                        label,
+                        label_details,
+                        filter_text: Some(format!("{}{}", trigger, sequence)),
+                        // preserve our ranking against alphabetic clients
+                        sort_text: Some(format!("{:04}", i)),
+                        detail: detail_template
+                            .as_ref()
+                            .map(|t| render_template(t, prefix, &s))
+                            .or_else(|| fallback_source.as_ref().map(|f| format!("from {}", f))),
+                        kind: Some(CompletionItemKind::TEXT),
+                        // documentation is deliberately absent here; clients
+                        // fetch it per item via `completionItem/resolve`
+                        text_edit,
+                        insert_text,
+                        insert_text_format: snippet.then_some(InsertTextFormat::SNIPPET),
+                        // lets us learn which candidates actually get picked
+                        command: Some(Command {
+                            title: "record usage".to_string(),
+                            command: "aim.recordUsage".to_string(),
+                            arguments: Some(vec![serde_json::json!(sequence)]),
+                        }),
+                        // everything `completionItem/resolve` needs, so it
+                        // never depends on in-memory request state
+                        data: Some(serde_json::json!({
+                            "sequence": prefix,
+                            "symbol": s.as_ref(),
+                            "keymap": fallback_source.as_deref().unwrap_or("active"),
+                        })),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+
+            // `offerLiteral`: one more item that simply keeps the typed
+            // text, for users who sometimes want the macro spelling and
+            // sometimes the glyph
+            if !completion_items.is_empty()
+                && !prefix.is_empty()
+                && bound.is_none()
+                && self.settings.read().unwrap().offer_literal
+            {
+                completion_items.push(CompletionItem {
+                    label: format!("{}{} (keep as typed)", trigger, prefix),
+                    kind: Some(CompletionItemKind::TEXT),
+                    filter_text: Some(format!("{}{}", trigger, prefix)),
+                    // after every real candidate, whatever the client sorts by
+                    sort_text: Some("~".to_string()),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range: Range {
+                            start: Position {
+                                line: position.line,
+                                character: position.character
+                                    - text::width(prefix, self.encoding()) as u32
+                                    - trigger_width,
+                            },
+                            end: Position {
+                                line: position.line,
+                                character: position.character
+                                    + text::width(&tail, self.encoding()) as u32,
+                            },
+                        },
+                        new_text: format!("{}{}{}", trigger, prefix, tail),
+                    })),
+                    ..Default::default()
+                });
+            }
+
+            // a dead-end prefix gets "did you mean" items for the closest
+            // known sequences instead of an empty popup; the label carries
+            // the corrected spelling, the edit inserts its symbol
+            if completion_items.is_empty() && bound.is_none() && prefix.chars().count() >= 3 {
+                for (seq, sym) in did_you_mean(&active.entries(), prefix) {
+                    completion_items.push(CompletionItem {
+                        label: format!("did you mean {}{}?", trigger, seq),
+                        label_details: self.supports_label_details().then(|| {
+                            CompletionItemLabelDetails {
+                                detail: None,
+                                description: Some(display_symbol(&sym)),
+                            }
+                        }),
+                        kind: Some(CompletionItemKind::TEXT),
+                        filter_text: Some(format!("{}{}", trigger, prefix)),
+                        detail: Some(format!("{}{} → {}", trigger, seq, sym)),
+                        text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                            range: Range {
+                                start: Position {
+                                    line: position.line,
+                                    character: position.character
+                                        - text::width(prefix, self.encoding()) as u32
+                                        - trigger_width,
+                                },
+                                end: Position {
+                                    line: position.line,
+                                    character: position.character
+                                        + text::width(&tail, self.encoding()) as u32,
+                                },
+                            },
+                            new_text: self.normalize(&sym),
+                        })),
+                        data: Some(serde_json::json!({
+                            "sequence": seq,
+                            "symbol": sym,
+                            "keymap": "active",
+                        })),
+                        ..Default::default()
+                    });
+                }
+            }
+
+            // make a truncated list visible as such: a non-insertable tail
+            // item telling users why the symbol they expect isn't shown
+            if overflow > 0 {
+                completion_items.push(CompletionItem {
+                    // a complete CJK syllable can't be narrowed by typing
+                    // on; `aim.pick` pages through the rest instead
+                    label: if numbered {
+                        format!("… {} more, page with aim.pick", overflow)
+                    } else {
+                        format!("… {} more, keep typing", overflow)
+                    },
+                    kind: Some(CompletionItemKind::TEXT),
+                    insert_text: Some(String::new()),
+                    filter_text: Some(prefix.to_string()),
+                    ..Default::default()
+                });
+            }
+
+            // the aim.logLevel setting still silences per-request logging
+            // for a workspace without touching the process-wide filter
+            if self.settings.read().unwrap().log_level != "off" {
+                tracing::debug!(
+                    "completion for {} ({} candidates)",
+                    prefix,
+                    completion_items.len()
+                );
+            }
+
+            self.metrics.record(started);
+
+            // an incomplete list makes the client re-query as the user types,
+            // so capped prefixes narrow instead of going stale
+            Ok(Some(CompletionResponse::List(CompletionList {
+                is_incomplete: overflow > 0,
+                items: completion_items,
+            })))
+        } else {
+            tracing::debug!("completion: no trigger left of the cursor");
+            Ok(None)
+        }
+    }
+
+    /// Reconstruct documentation purely from the item's `data` payload, so
+    /// resolution stays correct across keymap reloads and in daemon mode
+    /// where the originating session may differ.
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        if let Some(sym) = item
+            .data
+            .as_ref()
+            .and_then(|d| d.get("symbol"))
+            .and_then(|s| s.as_str())
+            && item.documentation.is_none()
+        {
+            let mut doc = unicode::describe(sym);
+            if let Some(x) = xref::describe(sym) {
+                doc.push('\n');
+                doc.push_str(&x);
+            }
+            item.documentation = Some(Documentation::String(doc));
+        }
+        Ok(item)
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // learned rankings survive the session
+        if let Some(path) = stats_path() {
+            let _ = self.stats.save(&path);
+        }
+        Ok(())
+    }
+}
+
+/// Append `sequence` -> `symbol` to the keymap file at `path` (native
+/// nested JSON), creating the file and intermediate nodes as needed and
+/// leaving everything else in it untouched.
+fn append_to_keymap(path: &Path, sequence: &str, symbol: &str) -> std::io::Result<()> {
+    let mut root: serde_json::Value = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&keymap::strip_jsonc(&raw)).ok())
+        .unwrap_or_else(|| serde_json::json!({}));
+    let mut node = &mut root;
+    for c in sequence.chars() {
+        if !node.is_object() {
+            *node = serde_json::json!({});
+        }
+        node = node
+            .as_object_mut()
+            .unwrap()
+            .entry(c.to_string())
+            .or_insert(serde_json::json!({}));
+    }
+    if !node.is_object() {
+        *node = serde_json::json!({});
+    }
+    let symbols = node
+        .as_object_mut()
+        .unwrap()
+        .entry(">>")
+        .or_insert(serde_json::json!([]));
+    if let Some(arr) = symbols.as_array_mut()
+        && !arr.iter().any(|s| s == symbol)
+    {
+        arr.push(serde_json::json!(symbol));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(
+        path,
+        serde_json::to_string_pretty(&root).unwrap_or_default(),
+    )
+}
+
+/// Where usage statistics are persisted between sessions.
+fn stats_path() -> Option<PathBuf> {
+    config::data_dir().map(|dir| dir.join("stats.json"))
+}
+
+/// Whether a replacement value carries `$1`-style snippet tabstops, making
+/// it a snippet entry (`\norm` → `‖$1‖` puts the cursor between the bars).
+fn is_snippet(sym: &str) -> bool {
+    let mut chars = sym.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$'
+            && chars
+                .peek()
+                .is_some_and(|n| n.is_ascii_digit() || *n == '{')
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// A snippet value with its tabstop markers removed, for clients without
+/// snippet support.
+fn strip_tabstops(sym: &str) -> String {
+    regex::Regex::new(r"\$\d+|\$\{[^}]*\}")
+        .map(|re| re.replace_all(sym, "").into_owned())
+        .unwrap_or_else(|_| sym.to_string())
+}
+
+/// The closest known sequences to a prefix that matched nothing, by edit
+/// distance, so `\lamda` still leads to `λ` instead of a dead end. Anything
+/// more than two edits away is noise; ties break alphabetically.
+fn did_you_mean(entries: &[(String, String)], prefix: &str) -> Vec<(String, String)> {
+    let mut scored: Vec<(usize, &(String, String))> = entries
+        .iter()
+        .filter(|(seq, _)| seq.len().abs_diff(prefix.len()) <= 2)
+        .map(|e| (fuzzy::edit_distance(&e.0, prefix), e))
+        .filter(|(d, _)| (1..=2).contains(d))
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.0.cmp(&b.1.0)));
+    scored.dedup_by(|a, b| a.1.0 == b.1.0);
+    scored
+        .into_iter()
+        .take(3)
+        .map(|(_, (seq, sym))| (seq.clone(), sym.clone()))
+        .collect()
+}
+
+/// A symbol as shown in labels: replacement values can be arbitrary
+/// strings, so line breaks become a visible ⏎ instead of wrecking the
+/// completion popup (the real newline still gets inserted).
+fn display_symbol(sym: &str) -> String {
+    sym.replace("\r\n", "⏎").replace(['\n', '\r'], "⏎")
+}
+
+/// A labelTemplate value without placeholders names a preset. The
+/// `screenReader` preset puts the spoken Unicode name first, so a reader
+/// announces "GREEK SMALL LETTER LAMDA" instead of skipping a glyph it
+/// has no pronunciation for; `verbose` adds the code point for disambiguating
+/// lookalikes by eye.
+fn resolve_label_template(template: &str) -> &str {
+    match template {
+        "screenReader" => "{name}, {sym} {seq}",
+        "verbose" => "{seq} {sym} {codepoint} {name}",
+        t => t,
+    }
+}
+
+/// Render a candidate label/detail template; placeholders `{seq}`, `{sym}`,
+/// `{name}`, `{codepoint}`.
+fn render_template(template: &str, seq: &str, sym: &str) -> String {
+    let mut out = template
+        .replace("{seq}", seq)
+        .replace("{sym}", &display_symbol(sym))
+        .replace("{codepoint}", &unicode::codepoints(sym));
+    if out.contains("{name}") {
+        out = out.replace("{name}", &unicode::names(sym));
+    }
+    out
+}
+
+/// Line comment syntax (open, close) for a languageId, for annotations
+/// appended at end of line.
+fn comment_syntax(language_id: &str) -> (&'static str, &'static str) {
+    match language_id {
+        "agda" | "haskell" | "lean" | "idris" | "elm" | "lua" | "sql" => ("--", ""),
+        "python" | "shellscript" | "ruby" | "perl" | "yaml" | "toml" | "r" | "julia" => ("#", ""),
+        "latex" | "tex" | "matlab" | "erlang" => ("%", ""),
+        "lisp" | "scheme" | "racket" | "clojure" => (";;", ""),
+        "html" | "xml" | "markdown" => ("<!--", " -->"),
+        "ocaml" | "coq" | "pascal" => ("(*", " *)"),
+        _ => ("//", ""),
+    }
+}
+
+/// Load everything the server would, poke it with a few canonical lookups,
+/// and print a pass/fail report. The first thing to ask users to run when
+/// something misbehaves.
+fn self_test() -> bool {
+    let mut ok = true;
+    let mut check = |name: &str, pass: bool| {
+        println!("{} ... {}", name, if pass { "ok" } else { "FAIL" });
+        ok &= pass;
+    };
+
+    let path = config::Env::load().keymap_path();
+    let keymap = Keymap::from_file(&path);
+    if let Err(e) = &keymap {
+        println!("{}: {}", path.display(), e);
+    }
+    check("keymap loads", keymap.is_ok());
+
+    if let Ok(keymap) = keymap {
+        let entries = keymap.entries();
+        check("keymap has entries", !entries.is_empty());
+        check(
+            "lookups round-trip",
+            entries
+                .iter()
+                .take(100)
+                .all(|(seq, sym)| keymap.lookup(seq).iter().any(|s| s.as_ref() == sym)),
+        );
+        let index = reverse::ReverseIndex::new(&entries);
+        check(
+            "reverse index round-trips",
+            entries
+                .iter()
+                .take(100)
+                .all(|(seq, sym)| index.lookup(sym).contains(seq)),
+        );
+    }
+
+    if Path::new("keymap.bin").exists() {
+        check(
+            "compiled keymap opens",
+            cache::CompiledKeymap::open(Path::new("keymap.bin")).is_some(),
+        );
+    }
+
+    println!("self test: {}", if ok { "pass" } else { "fail" });
+    ok
 }
 
-impl Keymap {
-    pub fn new(json: serde_json::Value) -> Self {
-        Self::load(&json).unwrap_or(Keymap {
-            here: vec![],
-            cont: HashMap::new(),
-        })
+/// Derive a draft input sequence for a character from its Unicode name,
+/// mirroring the keymap's conventions: short lowercase words, capitalized
+/// when the character is a capital letter.
+fn suggest_sequence(c: char) -> Option<String> {
+    let name = unicode::name(c);
+    if name.starts_with("U+") {
+        return None;
+    }
+    const NOISE: &[&str] = &[
+        "LATIN", "GREEK", "CYRILLIC", "SMALL", "CAPITAL", "LETTER", "SIGN", "MATHEMATICAL",
+        "WITH", "AND",
+    ];
+    let words: Vec<String> = name
+        .split_whitespace()
+        .filter(|w| !NOISE.contains(w))
+        .map(|w| w.to_lowercase())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let mut seq = words.join("-");
+    if name.contains("CAPITAL") {
+        let mut chars = seq.chars();
+        seq = chars.next()?.to_uppercase().collect::<String>() + chars.as_str();
     }
+    Some(seq)
+}
 
-    fn load(json: &serde_json::Value) -> Option<Self> {
-        json.as_object().map(|obj| {
-            let mut here = vec![];
-            let mut cont = HashMap::new();
-            if let Some(syms) = obj.get(">>").and_then(|a| a.as_array()) {
-                syms.iter()
-                    .for_each(|s| s.as_str().iter().for_each(|x| here.push(x.to_string())));
-            }
-            for (k, v) in obj {
-                if k != ">>" {
-                    if let Some(c) = k.chars().next() {
-                        Self::load(v).into_iter().for_each(|z| {
-                            cont.insert(c, z);
-                        });
-                    }
-                }
+/// `coverage <dir>`: scan a corpus and report the non-ASCII characters that
+/// have no input sequence in the keymap, most frequent first. Tells keymap
+/// maintainers what to add next. With `suggest`, also emit a mergeable
+/// keymap fragment with draft sequences derived from Unicode names.
+fn coverage(root: &Path, suggest: bool) -> bool {
+    let keymap = match Keymap::from_file(&config::Env::load().keymap_path()) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("coverage: {}", e);
+            return false;
+        }
+    };
+    let index = reverse::ReverseIndex::new(&keymap.entries());
+
+    let mut counts: HashMap<char, u64> = HashMap::new();
+    let mut builder = ignore::WalkBuilder::new(root);
+    builder.add_custom_ignore_filename(".aimignore");
+    for entry in builder.build().flatten() {
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        let Ok(text) = std::fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        for c in text.chars() {
+            if !c.is_ascii() && index.lookup(&c.to_string()).is_empty() {
+                *counts.entry(c).or_insert(0) += 1;
             }
-            Self { here, cont }
-        })
+        }
     }
 
-    pub fn lookup(&self, prefix: &str) -> Vec<String> {
-        self.get(&mut prefix.chars())
+    let mut missing: Vec<(char, u64)> = counts.into_iter().collect();
+    missing.sort_by_key(|&(c, n)| (std::cmp::Reverse(n), c));
+    for (c, n) in &missing {
+        println!("{:>8}  U+{:04X}  {}  {}", n, *c as u32, c, unicode::name(*c));
     }
+    println!("{} character(s) without an input sequence", missing.len());
 
-    fn get(&self, prefix: &mut Chars<'_>) -> Vec<String> {
-        fn flatten(map: &HashMap<char, Keymap>) -> Vec<String> {
-            let mut ret = vec![];
-            for k in map.values() {
-                ret.append(&mut k.here.clone());
-                ret.append(&mut flatten(&k.cont));
+    if suggest {
+        let mut fragment = serde_json::Map::new();
+        for &(c, _) in &missing {
+            if let Some(seq) = suggest_sequence(c)
+                && keymap.lookup(&seq).is_empty()
+                && !fragment.contains_key(&seq)
+            {
+                fragment.insert(seq, serde_json::Value::String(c.to_string()));
             }
-            ret
-        }
-        if let Some(c) = prefix.next() {
-            self.cont.get(&c).map_or(vec![], |k| k.get(prefix))
-        } else {
-            let mut ret = self.here.clone();
-            ret.append(&mut flatten(&self.cont));
-            ret
         }
+        let fragment = serde_json::Value::Object(fragment);
+        println!("{}", serde_json::to_string_pretty(&fragment).unwrap_or_default());
     }
+    true
 }
 
-#[derive(Debug)]
-struct Backend {
-    client: Client,
-    keymap: Keymap,
-    documents: DashMap<Url, String>,
+/// `check <file>`: parse a keymap and report structural problems with their
+/// path inside the trie, instead of the forgiving empty-on-error behavior
+/// the running server uses. Returns true when the file is clean.
+fn check(path: &Path) -> bool {
+    let problems = lint_keymap(path);
+    for p in &problems {
+        println!("{}: {}", path.display(), p);
+    }
+    if problems.is_empty() {
+        println!("{}: ok", path.display());
+    }
+    problems.is_empty()
 }
 
-#[tower_lsp::async_trait]
-impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
-        self.client
-            .log_message(MessageType::INFO, "aim server initialized!")
-            .await;
-
-        Ok(InitializeResult {
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
-                )),
-                completion_provider: Some(CompletionOptions {
-                    // resolve_provider: Some(true),
-                    trigger_characters: Some(('!'..='~').map(|s| s.to_string()).collect()),
-                    ..Default::default()
-                }),
-                ..Default::default()
+/// Every structural problem in a keymap file, each with its path inside the
+/// trie. Shared by the `check` subcommand and the `strictKeymap` load mode.
+fn lint_keymap(path: &Path) -> Vec<String> {
+    let mut problems: Vec<String> = vec![];
+    match path.extension().and_then(|e| e.to_str()) {
+        // foreign formats have their own parsers; their errors are the lint
+        Some("el") | Some("jl") | Some("toml") | Some("digraphs") => {
+            if let Err(e) = Keymap::from_file(path) {
+                problems.push(e);
+            }
+        }
+        _ => match std::fs::read_to_string(path) {
+            Err(e) => problems.push(e.to_string()),
+            Ok(raw) => match serde_json::from_str::<serde_json::Value>(&keymap::strip_jsonc(&raw))
+            {
+                Err(e) => problems.push(e.to_string()),
+                Ok(json) => {
+                    let mut entries = 0;
+                    if let Err(e) = Keymap::validate(&json, 0, &mut entries) {
+                        problems.push(e);
+                    }
+                    let mut seen = HashMap::new();
+                    check_node(
+                        &json,
+                        path.parent().unwrap_or(Path::new(".")),
+                        String::new(),
+                        &mut seen,
+                        &mut problems,
+                    );
+                }
             },
-            ..Default::default()
-        })
+        },
     }
+    problems
+}
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.documents
-            .insert(params.text_document.uri, params.text_document.text);
+fn check_node(
+    json: &serde_json::Value,
+    base: &Path,
+    seq: String,
+    seen: &mut HashMap<String, Vec<String>>,
+    problems: &mut Vec<String>,
+) {
+    let Some(obj) = json.as_object() else {
+        problems.push(format!(
+            "`{}` maps to {}, expected an object, symbol list or sub-file",
+            seq, json
+        ));
+        return;
+    };
+    for (key, value) in obj {
+        if key == "$include" {
+            let files: Vec<&serde_json::Value> = match value {
+                serde_json::Value::Array(files) => files.iter().collect(),
+                file @ serde_json::Value::String(_) => vec![file],
+                other => {
+                    problems.push(format!("`{}`: `$include` must name files, got {}", seq, other));
+                    continue;
+                }
+            };
+            for file in files {
+                match file.as_str() {
+                    Some(f) if !base.join(f).is_file() => {
+                        problems.push(format!("`{}`: included file {} does not exist", seq, f));
+                    }
+                    Some(_) => {}
+                    None => {
+                        problems.push(format!("`{}`: `$include` must name files, got {}", seq, file));
+                    }
+                }
+            }
+        } else if key == ">>" {
+            let Some(arr) = value.as_array() else {
+                problems.push(format!("`{}`: `>>` must be an array of symbols", seq));
+                continue;
+            };
+            if arr.is_empty() {
+                problems.push(format!("`{}`: empty `>>` array", seq));
+            }
+            let mut symbols = vec![];
+            for s in arr {
+                match s.as_str().or_else(|| s.get("symbol").and_then(|x| x.as_str())) {
+                    Some(sym) => symbols.push(sym.to_string()),
+                    None => problems.push(format!("`{}`: non-string symbol {}", seq, s)),
+                }
+            }
+            // the same sequence can be spelled as one multi-char key or as
+            // nested single chars; both ending somewhere different is a bug
+            if let Some(earlier) = seen.get(&seq) {
+                if earlier != &symbols {
+                    problems.push(format!(
+                        "`{}` defined twice, as {:?} and {:?}",
+                        seq, earlier, symbols
+                    ));
+                }
+            } else {
+                seen.insert(seq.clone(), symbols);
+            }
+        } else if let Some(file) = value.as_str() {
+            if !base.join(file).is_file() {
+                problems.push(format!(
+                    "`{}{}`: sub-file {} does not exist",
+                    seq, key, file
+                ));
+            }
+        } else {
+            check_node(value, base, format!("{}{}", seq, key), seen, problems);
+        }
     }
+}
 
-    async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        self.documents.insert(
-            params.text_document.uri,
-            params.content_changes[0].text.clone(),
-        );
+/// `cheatsheet`: the merged keymap as a printable reference, one row per
+/// sequence with its symbol and Unicode name, grouped by the first
+/// character of the sequence so related entries (arrows under `-`, Greek
+/// under `G`) land together.
+fn cheatsheet(keymap: &Keymap, html: bool) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    }
+    let mut entries = keymap.entries();
+    entries.sort();
+    let mut groups: std::collections::BTreeMap<char, Vec<(String, String)>> = Default::default();
+    for (seq, sym) in entries {
+        let Some(c) = seq.chars().next() else { continue };
+        groups.entry(c).or_default().push((seq, sym));
+    }
+    let mut out = String::new();
+    if html {
+        out.push_str(concat!(
+            "<!doctype html>\n<html><head><meta charset=\"utf-8\">\n",
+            "<title>naive-input-lsp cheat sheet</title></head>\n<body>\n",
+            "<h1>naive-input-lsp cheat sheet</h1>\n"
+        ));
+        for (c, rows) in &groups {
+            out.push_str(&format!(
+                "<h2>{}</h2>\n<table>\n<tr><th>Sequence</th><th>Symbol</th><th>Name</th></tr>\n",
+                escape(&c.to_string())
+            ));
+            for (seq, sym) in rows {
+                out.push_str(&format!(
+                    "<tr><td><code>\\{}</code></td><td>{}</td><td>{}</td></tr>\n",
+                    escape(seq),
+                    escape(sym),
+                    unicode::names(sym)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+        out.push_str("</body></html>\n");
+    } else {
+        out.push_str("# naive-input-lsp cheat sheet\n");
+        for (c, rows) in &groups {
+            out.push_str(&format!(
+                "\n## {}\n\n| Sequence | Symbol | Name |\n| --- | --- | --- |\n",
+                c
+            ));
+            for (seq, sym) in rows {
+                out.push_str(&format!(
+                    "| `\\{}` | {} | {} |\n",
+                    seq.replace('|', "\\|"),
+                    sym.replace('|', "\\|"),
+                    unicode::names(sym)
+                ));
+            }
+        }
     }
+    out
+}
 
-    async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        self.documents.remove(&params.text_document.uri);
+/// Starter config written by `config init` and printed by
+/// `--print-default-config`; everything commented out so the defaults stay
+/// in one place (the code).
+const DEFAULT_CONFIG: &str = r#"# naive-input-lsp server configuration.
+# Merged below environment variables, CLI flags and editor
+# initializationOptions; later sources win per key.
+
+# Match sequences case-insensitively (case-exact matches still rank first).
+# caseInsensitive = true
+
+# Completion label template; placeholders {seq}, {sym}, {name}, {codepoint}.
+# labelTemplate = "{seq} {sym}"
+# ...or a preset: "screenReader" announces the Unicode name first.
+# labelTemplate = "screenReader"
+
+# Expand escape sequences on save in matching files.
+# expandOnSave = ["**/*.agda"]
+
+# Offer the typed sequence itself as the last completion item.
+# offerLiteral = true
+
+# Insert with plain insertText instead of text edits, for clients that
+# ignore edits and paste the label verbatim.
+# insertTextFallback = true
+
+# Extra keymaps tried when the active one has no match.
+# fallbackKeymaps = ["~/.config/naive-input/personal.json"]
+
+# Built-in sequences to remove from the merged keymap.
+# disabledSequences = ["to"]
+
+# Search official Unicode character names when nothing else matches.
+# unicodeNameSearch = true
+
+# Report every structural problem in loaded keymaps instead of silently
+# dropping the entries involved.
+# strictKeymap = true
+
+# Input-mode leaders.
+# pinyinLeader = "py:"
+# romajiLeader = "jp:"
+# katakanaLeader = "jpk:"
+# hangulLeader = "kr:"
+
+# Log verbosity: "off" silences informational messages.
+# logLevel = "info"
+
+# Lint levels: "off", "hint", "info", "warning" or "error".
+# [diagnosticRules]
+# unexpandedSequence = "hint"
+# confusable = "warning"
+# bidiControl = "warning"
+# denylist = "error"
+"#;
+
+/// Example keymap written beside the starter config.
+const EXAMPLE_KEYMAP: &str = r#"{
+  "to": "→",
+  "forall": "∀",
+  "lambda": "λ",
+  "le": "≤",
+  "qed": "∎"
+}
+"#;
+
+/// `config init`: write the commented starter config and a small example
+/// keymap under the platform config directory. Never overwrites.
+fn config_init() -> bool {
+    let Some(dir) = config::config_dir() else {
+        eprintln!("config init: cannot determine the config directory");
+        return false;
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("config init: {}: {}", dir.display(), e);
+        return false;
     }
+    let mut ok = true;
+    for (name, contents) in [
+        ("config.toml", DEFAULT_CONFIG),
+        ("example-keymap.json", EXAMPLE_KEYMAP),
+    ] {
+        let path = dir.join(name);
+        if path.exists() {
+            println!("{} already exists, leaving it alone", path.display());
+            continue;
+        }
+        match std::fs::write(&path, contents) {
+            Ok(()) => println!("wrote {}", path.display()),
+            Err(e) => {
+                eprintln!("config init: {}: {}", path.display(), e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
 
-    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
+/// State every connection of a daemon shares: the parsed keymap, the
+/// compiled mapping, the reverse index and the usage store. Heavyweight
+/// keymaps are parsed once per machine, not once per editor.
+#[derive(Clone)]
+struct SharedState {
+    keymap: Arc<Keymap>,
+    compiled: Option<Arc<cache::CompiledKeymap>>,
+    reverse: Arc<reverse::ReverseIndex>,
+    stats: Arc<stats::UsageStats>,
+    /// Why the startup keymap could not be used, reported to each client
+    /// once it attaches; the server runs on the built-in table meanwhile.
+    startup_error: Option<String>,
+}
 
-        let mut document = self.documents.get(&uri);
+/// Build the LSP service for one connection on top of the shared state.
+/// Document store, settings and caches are fresh per connection.
+fn build_service(shared: SharedState) -> (LspService<Backend>, tower_lsp::ClientSocket) {
+    LspService::build(move |client| Backend {
+        client,
+        keymap: RwLock::new(shared.keymap),
+        compiled: shared.compiled,
+        reverse: shared.reverse,
+        documents: Arc::new(DashMap::new()),
+        line_index: DashMap::new(),
+        versions: DashMap::new(),
+        languages: DashMap::new(),
+        lang_keymaps: DashMap::new(),
+        file_keymaps: DashMap::new(),
+        folder_keymaps: DashMap::new(),
+        stats: shared.stats,
+        metrics: Metrics::default(),
+        flavors: RwLock::new(HashMap::new()),
+        pinyin: OnceLock::new(),
+        fuzzy_index: RwLock::new(None),
+        flat_trie: RwLock::new(None),
+        keymap_origins: RwLock::new(HashMap::new()),
+        trigger_keymaps: RwLock::new(HashMap::new()),
+        last_rebuild_error: RwLock::new(None),
+        startup_error: shared.startup_error.clone(),
+        last_conversion: DashMap::new(),
+        profile: RwLock::new(None),
+        init_options: RwLock::new(None),
+        zhuyin: OnceLock::new(),
+        cangjie: OnceLock::new(),
+        capabilities: OnceLock::new(),
+        encoding: OnceLock::new(),
+        settings: RwLock::new(config::Settings::default()),
+        roots: RwLock::new(vec![]),
+        published_diags: Arc::new(DashMap::new()),
+        diag_revision: Arc::new(DashMap::new()),
+    })
+    .custom_method("aim/exportStats", Backend::export_stats)
+    .custom_method("aim/stats", Backend::runtime_stats)
+    .custom_method("aim/scan", Backend::scan_request)
+    .custom_method("aim/lookup", Backend::lookup_request)
+    .custom_method("aim/status", Backend::status)
+    .custom_method("aim/keymap", Backend::dump_keymap)
+    .custom_method("aim/reverseLookup", Backend::reverse_lookup)
+    .custom_method("aim/tryKeymap", Backend::try_keymap)
+    .custom_method("aim/browse", Backend::browse)
+    .custom_method("notebookDocument/didOpen", Backend::notebook_did_open)
+    .custom_method("notebookDocument/didChange", Backend::notebook_did_change)
+    .custom_method("notebookDocument/didSave", Backend::notebook_did_save)
+    .custom_method("notebookDocument/didClose", Backend::notebook_did_close)
+    .finish()
+}
 
-        let line = document
-            .as_mut()
-            .and_then(|d| d.lines().nth(position.line as usize))
-            .map(|l| {
-                l.chars()
-                    .take(position.character as usize)
-                    .collect::<String>()
-            });
+/// Serve editor connections accepted on a Unix socket, all backed by the
+/// same `SharedState`. With an idle timeout, the daemon removes its socket
+/// and exits once it has spent that long with no connected clients, instead
+/// of lingering as a forgotten background process.
+#[cfg(unix)]
+async fn serve_daemon(
+    path: &str,
+    shared: SharedState,
+    idle: Option<std::time::Duration>,
+) -> tokio::io::Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)?;
+    eprintln!("aim: daemon listening on {}", path);
+    let active = Arc::new(AtomicUsize::new(0));
+    loop {
+        let accepted = match idle {
+            Some(timeout) => tokio::select! {
+                res = listener.accept() => Some(res?),
+                _ = tokio::time::sleep(timeout) => None,
+            },
+            None => Some(listener.accept().await?),
+        };
+        let Some((stream, _)) = accepted else {
+            // the idle timer fired; only shut down with no one connected
+            if active.load(Ordering::SeqCst) == 0 {
+                eprintln!("aim: idle, shutting down");
+                let _ = std::fs::remove_file(path);
+                return Ok(());
+            }
+            continue;
+        };
+        let shared = shared.clone();
+        active.fetch_add(1, Ordering::SeqCst);
+        let active = active.clone();
+        tokio::spawn(async move {
+            let (read, write) = stream.into_split();
+            let (service, socket) = build_service(shared);
+            Server::new(read, write, socket).serve(service).await;
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
 
-        let prefix = line.as_ref().and_then(|l| l.rsplit_once('\\'));
+/// Serve editor connections on TCP (`--port <n>`), for remote or container
+/// setups where stdio and unix sockets don't reach, and as the daemon mode
+/// that works on every platform.
+async fn serve_tcp(
+    port: u16,
+    shared: SharedState,
+    idle: Option<std::time::Duration>,
+) -> tokio::io::Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-        if let Some((_, prefix)) = prefix {
-            if prefix.len() == 0 {
-                return Ok(None);
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    eprintln!("aim: listening on {}", listener.local_addr()?);
+    let active = Arc::new(AtomicUsize::new(0));
+    loop {
+        let accepted = match idle {
+            Some(timeout) => tokio::select! {
+                res = listener.accept() => Some(res?),
+                _ = tokio::time::sleep(timeout) => None,
+            },
+            None => Some(listener.accept().await?),
+        };
+        let Some((stream, _)) = accepted else {
+            // the idle timer fired; only shut down with no one connected
+            if active.load(Ordering::SeqCst) == 0 {
+                eprintln!("aim: idle, shutting down");
+                return Ok(());
             }
-            let completion_items: Vec<CompletionItem> = self
-                .keymap
-                .lookup(prefix)
-                .into_iter()
-                .map(|s| CompletionItem {
-                    label: format!("{} {}", prefix, &s),
-                    kind: Some(CompletionItemKind::TEXT),
-                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
-                        range: Range {
-                            start: Position {
-                                line: position.line,
-                                character: position.character - (prefix.len() as u32) - 1,
-                            },
-                            end: position,
-                        },
-                        new_text: s,
-                    })),
-                    ..Default::default()
-                })
-                .collect();
+            continue;
+        };
+        let shared = shared.clone();
+        active.fetch_add(1, Ordering::SeqCst);
+        let active = active.clone();
+        tokio::spawn(async move {
+            let (read, write) = stream.into_split();
+            let (service, socket) = build_service(shared);
+            Server::new(read, write, socket).serve(service).await;
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
 
-            self.client
-                .log_message(MessageType::INFO, format!("completion for {}", prefix))
-                .await;
+/// Serve one editor over a unix domain socket (`--socket <path>`): bind,
+/// take the first connection, exit when it closes — the spawned-per-editor
+/// model VS Code and Neovim expect, avoiding stdio quirks when the server
+/// is wrapped by other tooling. `--daemon` is the multi-editor variant;
+/// `--pipe` is the Windows counterpart.
+#[cfg(unix)]
+async fn serve_socket(path: &str, shared: SharedState) -> tokio::io::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)?;
+    eprintln!("aim: listening on {}", path);
+    let (stream, _) = listener.accept().await?;
+    let (read, write) = stream.into_split();
+    let (service, socket) = build_service(shared);
+    Server::new(read, write, socket).serve(service).await;
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
 
-            Ok(Some(CompletionResponse::Array(completion_items)))
-        } else {
-            Ok(None)
-        }
+/// Serve browser editors over WebSocket (`--websocket <port>`): each WS
+/// message carries one JSON-RPC payload, the framing Monaco/CodeMirror LSP
+/// adapters speak, so web editors get the input method without a native
+/// process per tab. The bridge adds and strips the byte-stream
+/// `Content-Length` headers around the usual service.
+async fn serve_websocket(port: u16, shared: SharedState) -> tokio::io::Result<()> {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    eprintln!("aim: websocket on {}", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let Ok(ws) = tokio_tungstenite::accept_async(stream).await else {
+                return;
+            };
+            let (mut sink, mut source) = ws.split();
+            let (service, socket) = build_service(shared);
+            let (ws_side, server_side) = tokio::io::duplex(1 << 16);
+            let (server_read, server_write) = tokio::io::split(server_side);
+            tokio::spawn(async move {
+                Server::new(server_read, server_write, socket)
+                    .serve(service)
+                    .await;
+            });
+            let (mut ws_read, mut ws_write) = tokio::io::split(ws_side);
+            // inbound: one WS message becomes one framed LSP message
+            let inbound = async move {
+                while let Some(Ok(msg)) = source.next().await {
+                    let data = match msg {
+                        Message::Text(_) | Message::Binary(_) => msg.into_data(),
+                        Message::Close(_) => break,
+                        _ => continue,
+                    };
+                    let header = format!("Content-Length: {}\r\n\r\n", data.len());
+                    if ws_write.write_all(header.as_bytes()).await.is_err()
+                        || ws_write.write_all(&data).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            };
+            // outbound: peel complete frames off the byte stream and send
+            // each body as its own WS message
+            let outbound = async move {
+                let mut buf: Vec<u8> = vec![];
+                let mut chunk = [0u8; 4096];
+                loop {
+                    let n = match ws_read.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    buf.extend_from_slice(&chunk[..n]);
+                    while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let headers = String::from_utf8_lossy(&buf[..end]).to_string();
+                        let length = headers
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length:"))
+                            .and_then(|v| v.trim().parse::<usize>().ok());
+                        let Some(length) = length else {
+                            buf.drain(..end + 4);
+                            continue;
+                        };
+                        if buf.len() < end + 4 + length {
+                            break;
+                        }
+                        let body =
+                            String::from_utf8_lossy(&buf[end + 4..end + 4 + length]).into_owned();
+                        buf.drain(..end + 4 + length);
+                        if sink.send(Message::Text(body)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            };
+            tokio::join!(inbound, outbound);
+        });
     }
+}
 
-    async fn shutdown(&self) -> Result<()> {
-        Ok(())
+/// Serve editor connections on a Windows named pipe
+/// (`--pipe \\.\pipe\naive-input`), the transport VS Code's language client
+/// expects where TCP and stdio aren't viable.
+#[cfg(windows)]
+async fn serve_pipe(name: &str, shared: SharedState) -> tokio::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(name)?;
+    eprintln!("aim: listening on {}", name);
+    loop {
+        server.connect().await?;
+        let stream = server;
+        server = ServerOptions::new().create(name)?;
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let (read, write) = tokio::io::split(stream);
+            let (service, socket) = build_service(shared);
+            Server::new(read, write, socket).serve(service).await;
+        });
     }
 }
 
+/// naive `\sequence` unicode input as an LSP server
+#[derive(clap::Parser)]
+#[command(name = "aim-lsp", version)]
+struct Cli {
+    /// keymap file, overriding AIM_KEYMAP and the config file
+    #[arg(long, value_name = "FILE", global = true)]
+    keymap: Option<PathBuf>,
+    /// serve over stdio (the default; wins over the other transports)
+    #[arg(long)]
+    stdio: bool,
+    /// serve editors over TCP on 127.0.0.1
+    #[arg(long, value_name = "PORT")]
+    port: Option<u16>,
+    /// bridge JSON-RPC over WebSocket for browser editors
+    #[arg(long, value_name = "PORT")]
+    websocket: Option<u16>,
+    /// serve one editor over a unix domain socket, exiting when it closes
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<String>,
+    /// keep serving editors on a unix socket
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "/tmp/aim-lsp.sock")]
+    daemon: Option<String>,
+    /// serve editors on a Windows named pipe
+    #[cfg(windows)]
+    #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = r"\\.\pipe\naive-input")]
+    pipe: Option<String>,
+    /// minutes without a connection before a daemon exits on its own
+    #[arg(long, value_name = "MINUTES")]
+    idle_timeout: Option<u64>,
+    /// append server diagnostics to FILE instead of stderr
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    /// error, warn, info or debug
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    log_level: String,
+    /// exercise the embedded keymap against itself and exit
+    #[arg(long)]
+    self_test: bool,
+    /// print the default config TOML and exit
+    #[arg(long)]
+    print_default_config: bool,
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+
+#[derive(clap::Subcommand)]
+enum Cmd {
+    /// report which \sequences a project uses but the keymap lacks
+    Coverage {
+        root: Option<PathBuf>,
+        /// also print near-miss keymap entries for each gap
+        #[arg(long)]
+        suggest: bool,
+    },
+    /// validate a keymap file without starting the server
+    Check { file: PathBuf },
+    /// precompile the keymap into the mmap format, so huge tables skip
+    /// JSON parsing and trie construction on startup
+    Compile { out: Option<PathBuf> },
+    /// rewrite a foreign table (agda-input, Julia, flat JSON, Vim
+    /// digraphs, TOML) as the native trie JSON
+    Convert {
+        table: PathBuf,
+        out: Option<PathBuf>,
+    },
+    /// print the symbols a prefix expands to: usable from scripts and fzf
+    Lookup { prefix: String },
+    /// export the merged keymap as a printable cheat sheet, grouped by
+    /// sequence prefix, one row per `\sequence` with its symbol and
+    /// Unicode name
+    Cheatsheet {
+        /// write HTML instead of Markdown
+        #[arg(long)]
+        html: bool,
+        out: Option<PathBuf>,
+    },
+    /// config management
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigAction {
+    /// write a starter config file to the standard location
+    Init,
+}
+
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    let raw = tokio::fs::read("keymap.json").await?;
-    let keymap = Keymap::new(serde_json::from_str(
-        std::str::from_utf8(&raw).unwrap_or(""),
-    )?);
+    use clap::Parser;
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        keymap,
-        documents: DashMap::new(),
-    });
+    let cli = Cli::parse();
+    if cli.self_test {
+        std::process::exit(if self_test() { 0 } else { 1 });
+    }
+    if cli.print_default_config {
+        print!("{}", DEFAULT_CONFIG);
+        std::process::exit(0);
+    }
+    let keymap_path = cli
+        .keymap
+        .clone()
+        .unwrap_or_else(|| config::Env::load().keymap_path());
+
+    match cli.command {
+        Some(Cmd::Coverage { root, suggest }) => {
+            let root = root.unwrap_or_else(|| PathBuf::from("."));
+            std::process::exit(if coverage(&root, suggest) { 0 } else { 1 });
+        }
+        Some(Cmd::Check { file }) => {
+            std::process::exit(if check(&file) { 0 } else { 1 });
+        }
+        Some(Cmd::Compile { out }) => {
+            let keymap = Keymap::from_file(&keymap_path).unwrap_or_else(|e| {
+                eprintln!("aim: {}: {}", keymap_path.display(), e);
+                std::process::exit(1);
+            });
+            let out = out.unwrap_or_else(|| PathBuf::from("keymap.bin"));
+            let entries = keymap.entries();
+            println!("compiled {} entries to {}", entries.len(), out.display());
+            std::fs::write(&out, cache::encode(entries))?;
+            std::process::exit(0);
+        }
+        Some(Cmd::Convert { table, out }) => {
+            // a .json input here is the flat format; nested files need no
+            // convert
+            let loaded = if table.extension().is_some_and(|e| e == "json") {
+                std::fs::read_to_string(&table)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| keymap::parse_flat_json(&text))
+                    .map(Keymap::from_flat_table)
+            } else {
+                Keymap::from_file(&table)
+            };
+            let keymap = loaded.unwrap_or_else(|e| {
+                eprintln!("aim: {}: {}", table.display(), e);
+                std::process::exit(1);
+            });
+            let json = serde_json::to_string_pretty(&keymap.to_json()).unwrap_or_default();
+            match out {
+                Some(path) => std::fs::write(path, json + "\n")?,
+                None => println!("{}", json),
+            }
+            std::process::exit(0);
+        }
+        Some(Cmd::Lookup { prefix }) => {
+            let prefix = prefix.strip_prefix('\\').unwrap_or(&prefix);
+            let mut keymap = Keymap::embedded();
+            if let Ok(external) = Keymap::from_file(&keymap_path) {
+                keymap.merge(external);
+            }
+            let symbols = keymap.lookup(prefix);
+            for s in &symbols {
+                println!("{}", s);
+            }
+            std::process::exit(if symbols.is_empty() { 1 } else { 0 });
+        }
+        Some(Cmd::Cheatsheet { html, out }) => {
+            let mut keymap = Keymap::embedded();
+            if let Ok(external) = Keymap::from_file(&keymap_path) {
+                keymap.merge(external);
+            }
+            let sheet = cheatsheet(&keymap, html);
+            match out {
+                Some(path) => std::fs::write(path, sheet)?,
+                None => print!("{}", sheet),
+            }
+            std::process::exit(0);
+        }
+        Some(Cmd::Config {
+            action: ConfigAction::Init,
+        }) => {
+            std::process::exit(if config_init() { 0 } else { 1 });
+        }
+        None => {}
+    }
+
+    // --log-file/--log-level configure the tracing sink; stderr is the
+    // default so the stdio transport keeps stdout to itself
+    let level = cli
+        .log_level
+        .parse()
+        .unwrap_or(tracing::Level::INFO);
+    match &cli.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(std::sync::Arc::new(file))
+                .init();
+        }
+        None => {
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_target(false)
+                .with_writer(std::io::stderr)
+                .init();
+        }
+    }
+    tracing::info!("aim-lsp {} starting", env!("CARGO_PKG_VERSION"));
+    let mut keymap = Keymap::embedded();
+    let mut startup_error = None;
+    match Keymap::from_file(&keymap_path) {
+        Ok(external) => {
+            keymap.merge(external);
+            tracing::info!("loaded {} ({} entries)", keymap_path.display(), keymap.entries().len());
+        }
+        Err(e) => {
+            tracing::warn!(
+                "cannot load {}: {}; falling back to the built-in keymap",
+                keymap_path.display(),
+                e
+            );
+            // an absent implicit file is normal; a malformed one, or a
+            // missing explicitly-requested one, deserves a client popup
+            if cli.keymap.is_some() || keymap_path.exists() {
+                startup_error = Some(format!("cannot load {}: {}", keymap_path.display(), e));
+            }
+        }
+    }
+
+    let shared = SharedState {
+        reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+        keymap: Arc::new(keymap),
+        // only trust the compiled cache while it is newer than its source
+        compiled: cache::is_fresh(Path::new("keymap.bin"), &keymap_path)
+            .then(|| cache::CompiledKeymap::open(Path::new("keymap.bin")))
+            .flatten()
+            .map(Arc::new),
+        stats: Arc::new(
+            stats_path()
+                .map(|p| stats::UsageStats::load(&p))
+                .unwrap_or_default(),
+        ),
+        startup_error,
+    };
+
+    // shared by the daemon transports: minutes without a connection before
+    // the server exits on its own
+    let idle = cli
+        .idle_timeout
+        .map(|minutes| std::time::Duration::from_secs(minutes * 60));
+
+    // an explicit --stdio wins over any other transport flag
+    if !cli.stdio {
+        if let Some(port) = cli.port {
+            return serve_tcp(port, shared, idle).await;
+        }
 
+        if let Some(port) = cli.websocket {
+            return serve_websocket(port, shared).await;
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &cli.socket {
+            return serve_socket(path, shared).await;
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &cli.daemon {
+            return serve_daemon(path, shared, idle).await;
+        }
+
+        #[cfg(windows)]
+        if let Some(name) = &cli.pipe {
+            return serve_pipe(name, shared).await;
+        }
+    }
+
+    let (service, socket) = build_service(shared);
     Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
         .serve(service)
         .await;
@@ -192,12 +3981,411 @@ mod test {
     use tokio::io;
 
     #[test]
-    fn test_lookup() -> io::Result<()> {
-        let raw = std::fs::read("keymap.json")?;
-        let json: serde_json::Value =
-            serde_json::from_str(&std::string::String::from_utf8(raw).unwrap_or("".to_string()))?;
-        let keymap = Keymap::new(json);
-        assert_eq!(keymap.lookup("Gl-"), vec!["ƛ"]);
+    fn test_session_isolation() {
+        let keymap = Arc::new(Keymap::new(serde_json::json!({ "to": "→" })));
+        let shared = SharedState {
+            reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+            keymap,
+            compiled: None,
+            stats: Arc::new(stats::UsageStats::default()),
+            startup_error: None,
+        };
+        let (a, _socket_a) = build_service(shared.clone());
+        let (b, _socket_b) = build_service(shared);
+
+        // document state is per session…
+        let uri = Url::parse("file:///tmp/a.agda").unwrap();
+        a.inner().documents.insert(uri.clone(), "x".to_string());
+        assert!(b.inner().documents.get(&uri).is_none());
+
+        // …while the keymap and the usage store are deliberately shared
+        assert!(Arc::ptr_eq(&a.inner().keymap(), &b.inner().keymap()));
+        assert!(Arc::ptr_eq(&a.inner().stats, &b.inner().stats));
+    }
+
+    #[tokio::test]
+    async fn test_did_change_applies_all_changes() {
+        let keymap = Arc::new(Keymap::new(serde_json::json!({})));
+        let shared = SharedState {
+            reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+            keymap,
+            compiled: None,
+            stats: Arc::new(stats::UsageStats::default()),
+            startup_error: None,
+        };
+        let (service, _socket) = build_service(shared);
+        let uri = Url::parse("file:///tmp/multi.agda").unwrap();
+        service.inner().documents.insert(uri.clone(), "ab\ncd\n".to_string());
+        let range = |l1, c1, l2, c2| Range::new(Position::new(l1, c1), Position::new(l2, c2));
+        service
+            .inner()
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: 2 },
+                content_changes: vec![
+                    // later changes are positioned in the text the earlier ones produced
+                    TextDocumentContentChangeEvent {
+                        range: Some(range(0, 1, 0, 2)),
+                        range_length: None,
+                        text: "X".to_string(),
+                    },
+                    TextDocumentContentChangeEvent {
+                        range: Some(range(1, 0, 1, 1)),
+                        range_length: None,
+                        text: "YY".to_string(),
+                    },
+                ],
+            })
+            .await;
+        assert_eq!(*service.inner().documents.get(&uri).unwrap(), "aX\nYYd\n");
+
+        // a notification with an older version than the last one is discarded
+        service.inner().versions.insert(uri.clone(), 5);
+        service
+            .inner()
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: 3 },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "stale".to_string(),
+                }],
+            })
+            .await;
+        assert_eq!(*service.inner().documents.get(&uri).unwrap(), "aX\nYYd\n");
+    }
+
+    /// Write one framed JSON-RPC message, the way an editor would.
+    async fn frame(w: &mut (impl tokio::io::AsyncWrite + Unpin), msg: serde_json::Value) {
+        use tokio::io::AsyncWriteExt;
+        let body = msg.to_string();
+        let head = format!("Content-Length: {}\r\n\r\n", body.len());
+        w.write_all(head.as_bytes()).await.unwrap();
+        w.write_all(body.as_bytes()).await.unwrap();
+    }
+
+    /// Read frames until the response with `id` arrives, acknowledging
+    /// server→client requests (progress token creation and the like) so the
+    /// server never blocks on us, and skipping its notifications.
+    async fn response(
+        r: &mut (impl tokio::io::AsyncRead + Unpin),
+        w: &mut (impl tokio::io::AsyncWrite + Unpin),
+        buf: &mut Vec<u8>,
+        id: i64,
+    ) -> serde_json::Value {
+        use tokio::io::AsyncReadExt;
+        loop {
+            while let Some(end) = buf.windows(4).position(|b| b == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buf[..end]).to_string();
+                let len = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length:"))
+                    .and_then(|v| v.trim().parse::<usize>().ok())
+                    .unwrap();
+                if buf.len() < end + 4 + len {
+                    break;
+                }
+                let msg: serde_json::Value =
+                    serde_json::from_slice(&buf[end + 4..end + 4 + len]).unwrap();
+                buf.drain(..end + 4 + len);
+                if msg.get("method").is_some() {
+                    if let Some(rid) = msg.get("id") {
+                        frame(w, serde_json::json!({ "jsonrpc": "2.0", "id": rid, "result": null }))
+                            .await;
+                    }
+                    continue;
+                }
+                if msg.get("id").and_then(|i| i.as_i64()) == Some(id) {
+                    return msg;
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            let n = tokio::time::timeout(std::time::Duration::from_secs(10), r.read(&mut chunk))
+                .await
+                .expect("server answered within 10s")
+                .unwrap();
+            assert!(n > 0, "server closed the stream");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// The whole editor conversation over an in-memory pipe: initialize →
+    /// didOpen → didChange → completion, asserting on the returned edit.
+    #[tokio::test]
+    async fn test_end_to_end_completion() {
+        let keymap = Arc::new(Keymap::embedded());
+        let shared = SharedState {
+            reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+            keymap,
+            compiled: None,
+            stats: Arc::new(stats::UsageStats::default()),
+            startup_error: None,
+        };
+        let (service, socket) = build_service(shared);
+        let (client_side, server_side) = tokio::io::duplex(1 << 16);
+        let (server_read, server_write) = tokio::io::split(server_side);
+        tokio::spawn(async move {
+            Server::new(server_read, server_write, socket)
+                .serve(service)
+                .await;
+        });
+        let (mut read, mut write) = tokio::io::split(client_side);
+        let mut buf = Vec::new();
+
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": { "capabilities": { "textDocument": { "completion": {} } } }
+            }),
+        )
+        .await;
+        let init = response(&mut read, &mut write, &mut buf, 1).await;
+        assert!(init["result"]["capabilities"]["completionProvider"].is_object());
+        frame(
+            &mut write,
+            serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        )
+        .await;
+
+        let uri = "file:///tmp/e2e.agda";
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": {
+                    "uri": uri, "languageId": "agda", "version": 1, "text": "x \\for"
+                } }
+            }),
+        )
+        .await;
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": uri, "version": 2 },
+                    "contentChanges": [{ "text": "x \\forall" }]
+                }
+            }),
+        )
+        .await;
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 2, "method": "textDocument/completion",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "position": { "line": 0, "character": 9 }
+                }
+            }),
+        )
+        .await;
+        let completion = response(&mut read, &mut write, &mut buf, 2).await;
+        // the edit replaces the whole `\forall` with the symbol
+        let edit = &completion["result"]["items"][0]["textEdit"];
+        assert_eq!(edit["newText"], "∀");
+        assert_eq!(edit["range"]["start"]["line"], 0);
+        assert_eq!(edit["range"]["start"]["character"], 2);
+        assert_eq!(edit["range"]["end"]["character"], 9);
+    }
+
+    /// With `escapeDoubledTrigger` off, a sequence may contain the trigger
+    /// itself: `\'\AE` must complete as the single entry `'\AE` → Ǽ, not as
+    /// a fresh sequence `AE` starting at the second backslash.
+    #[tokio::test]
+    async fn test_trigger_inside_sequence() {
+        let keymap = Arc::new(Keymap::embedded());
+        let shared = SharedState {
+            reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+            keymap,
+            compiled: None,
+            stats: Arc::new(stats::UsageStats::default()),
+            startup_error: None,
+        };
+        let (service, socket) = build_service(shared);
+        let (client_side, server_side) = tokio::io::duplex(1 << 16);
+        let (server_read, server_write) = tokio::io::split(server_side);
+        tokio::spawn(async move {
+            Server::new(server_read, server_write, socket)
+                .serve(service)
+                .await;
+        });
+        let (mut read, mut write) = tokio::io::split(client_side);
+        let mut buf = Vec::new();
+
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": {
+                    "capabilities": { "textDocument": { "completion": {} } },
+                    "initializationOptions": { "escapeDoubledTrigger": false }
+                }
+            }),
+        )
+        .await;
+        response(&mut read, &mut write, &mut buf, 1).await;
+        frame(
+            &mut write,
+            serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        )
+        .await;
+
+        let uri = "file:///tmp/trigger.tex";
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": {
+                    "uri": uri, "languageId": "latex", "version": 1, "text": "x \\'\\AE"
+                } }
+            }),
+        )
+        .await;
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 2, "method": "textDocument/completion",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "position": { "line": 0, "character": 7 }
+                }
+            }),
+        )
+        .await;
+        let completion = response(&mut read, &mut write, &mut buf, 2).await;
+        let edit = &completion["result"]["items"][0]["textEdit"];
+        assert_eq!(edit["newText"], "Ǽ");
+        // the edit spans the whole `\'\AE`, from the first backslash on
+        assert_eq!(edit["range"]["start"]["character"], 2);
+        assert_eq!(edit["range"]["end"]["character"], 7);
+    }
+
+    /// A scratch buffer never touches the disk; everything completion needs
+    /// must come from the document store, not from a resolvable path.
+    #[tokio::test]
+    async fn test_untitled_completion() {
+        let keymap = Arc::new(Keymap::embedded());
+        let shared = SharedState {
+            reverse: Arc::new(reverse::ReverseIndex::new(&keymap.entries())),
+            keymap,
+            compiled: None,
+            stats: Arc::new(stats::UsageStats::default()),
+            startup_error: None,
+        };
+        let (service, socket) = build_service(shared);
+        let (client_side, server_side) = tokio::io::duplex(1 << 16);
+        let (server_read, server_write) = tokio::io::split(server_side);
+        tokio::spawn(async move {
+            Server::new(server_read, server_write, socket)
+                .serve(service)
+                .await;
+        });
+        let (mut read, mut write) = tokio::io::split(client_side);
+        let mut buf = Vec::new();
+
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": { "capabilities": { "textDocument": { "completion": {} } } }
+            }),
+        )
+        .await;
+        response(&mut read, &mut write, &mut buf, 1).await;
+        frame(
+            &mut write,
+            serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        )
+        .await;
+
+        let uri = "untitled:Untitled-1";
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": {
+                    "uri": uri, "languageId": "plaintext", "version": 1, "text": "\\forall"
+                } }
+            }),
+        )
+        .await;
+        frame(
+            &mut write,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 2, "method": "textDocument/completion",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "position": { "line": 0, "character": 7 }
+                }
+            }),
+        )
+        .await;
+        let completion = response(&mut read, &mut write, &mut buf, 2).await;
+        let edit = &completion["result"]["items"][0]["textEdit"];
+        assert_eq!(edit["newText"], "∀");
+        assert_eq!(edit["range"]["end"]["character"], 7);
+    }
+
+    #[test]
+    fn test_append_to_keymap() -> io::Result<()> {
+        let dir = std::env::temp_dir().join("aim-lsp-test-append");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("keymap.json");
+        std::fs::write(&path, r#"{ "t": { "o": { ">>": ["→"] } } }"#)?;
+        append_to_keymap(&path, "top", "⊤")?;
+        let keymap = Keymap::from_file(&path).unwrap();
+        // the new entry lands without disturbing the existing one
+        assert_eq!(keymap.lookup("top"), vec!["⊤".into()]);
+        assert!(keymap.lookup("to").contains(&"→".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let entries = Keymap::embedded().entries();
+        let suggestions = did_you_mean(&entries, "lamda");
+        assert!(suggestions.iter().any(|(seq, _)| seq == "lambda"));
+        // far-off garbage suggests nothing rather than everything
+        assert!(did_you_mean(&entries, "qqqqqqqq").is_empty());
+    }
+
+    #[test]
+    fn test_label_template_presets() {
+        let preset = resolve_label_template("screenReader");
+        assert_eq!(
+            render_template(preset, "lambda", "λ"),
+            "GREEK SMALL LETTER LAMDA, λ lambda"
+        );
+        // anything with placeholders passes through untouched
+        assert_eq!(resolve_label_template("{sym} ({seq})"), "{sym} ({seq})");
+    }
+
+    #[test]
+    fn test_check() -> io::Result<()> {
+        assert!(check(Path::new("keymap.json")));
+
+        let dir = std::env::temp_dir().join("aim-lsp-test-check");
+        std::fs::create_dir_all(&dir)?;
+        let bad = dir.join("bad.json");
+        std::fs::write(
+            &bad,
+            r#"{ "t": { "o": { ">>": ["→"] } }, "to": { ">>": ["⟶"] }, "x": { ">>": [] } }"#,
+        )?;
+        assert!(!check(&bad));
         Ok(())
     }
+
+    #[test]
+    fn test_suggest_sequence() {
+        assert_eq!(suggest_sequence('λ'), Some("lamda".to_string()));
+        assert_eq!(suggest_sequence('Λ'), Some("Lamda".to_string()));
+    }
+
+    #[test]
+    fn test_display_symbol() {
+        assert_eq!(display_symbol("∎\n"), "∎⏎");
+        assert_eq!(display_symbol("a\r\nb"), "a⏎b");
+        assert_eq!(display_symbol("⟹ "), "⟹ ");
+    }
 }
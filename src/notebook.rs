@@ -0,0 +1,79 @@
+//! LSP 3.17 `notebookDocument/*` synchronization.
+//!
+//! The lsp-types version we build against predates notebooks, so the param
+//! shapes are declared here (only the parts we act on; serde skips the rest)
+//! and the notifications are registered as custom methods. Cells are plain
+//! text documents in the shared document store, so completion inside a cell
+//! just works with per-cell positions.
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::*;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocument {
+    pub uri: Url,
+    pub version: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentIdentifier {
+    pub uri: Url,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenNotebookDocumentParams {
+    pub notebook_document: NotebookDocument,
+    pub cell_text_documents: Vec<TextDocumentItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellStructureChange {
+    #[serde(default)]
+    pub did_open: Vec<TextDocumentItem>,
+    #[serde(default)]
+    pub did_close: Vec<TextDocumentIdentifier>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellTextChange {
+    pub document: VersionedTextDocumentIdentifier,
+    pub changes: Vec<TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellChanges {
+    pub structure: Option<NotebookCellStructureChange>,
+    #[serde(default)]
+    pub text_content: Vec<NotebookCellTextChange>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentChangeEvent {
+    pub cells: Option<NotebookCellChanges>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeNotebookDocumentParams {
+    pub change: NotebookDocumentChangeEvent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidSaveNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+    pub cell_text_documents: Vec<TextDocumentIdentifier>,
+}
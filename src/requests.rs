@@ -0,0 +1,147 @@
+//! Param and result types for the custom `aim/*` protocol extensions.
+
+use serde::{Deserialize, Serialize};
+
+/// `aim/tryKeymap`: run lookups against a keymap supplied in the request,
+/// without touching the live keymap. Lets keymap authors iterate from a
+/// scratch buffer with instant feedback.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TryKeymapParams {
+    pub keymap: serde_json::Value,
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+}
+
+/// `aim/browse`: page through the keymap grouped by Unicode block, with an
+/// optional filter, so plugins can build a symbol palette on top of the
+/// server instead of shipping their own copy of the data.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+#[derive(Default)]
+pub struct BrowseParams {
+    pub filter: Option<String>,
+    pub page: usize,
+    pub page_size: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowseEntry {
+    pub sequence: String,
+    pub symbol: String,
+    /// Unicode block of the symbol's first character.
+    pub category: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowseResult {
+    pub entries: Vec<BrowseEntry>,
+    pub total: usize,
+    pub page: usize,
+}
+
+/// `aim/lookup`: candidates for a bare prefix, no document position needed,
+/// so plugins can build their own pickers on top of the server.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupParams {
+    pub prefix: String,
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupResult {
+    pub symbols: Vec<String>,
+}
+
+/// `aim/reverseLookup`: for each character of `text`, the key sequences
+/// that produce it — the building block for "describe char at point".
+#[derive(Debug, Deserialize)]
+pub struct ReverseLookupParams {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReverseLookupEntry {
+    pub character: char,
+    pub sequences: Vec<String>,
+}
+
+/// `aim/keymap`: one row of the flattened effective keymap, with the layer
+/// that contributed it, for cheat-sheet panels and precedence debugging.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeymapEntry {
+    pub sequence: String,
+    pub symbol: String,
+    pub source: String,
+}
+
+/// `aim/scan`: every recognized escape sequence in a document, with its
+/// range and the candidate symbols of the longest match — the raw material
+/// for a preview/confirm UI over bulk conversion, instead of blindly
+/// applying the code action.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanParams {
+    pub uri: tower_lsp::lsp_types::Url,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanEntry {
+    pub range: tower_lsp::lsp_types::Range,
+    pub sequence: String,
+    pub candidates: Vec<String>,
+}
+
+/// `aim/stats`: runtime metrics — entries contributed per keymap source,
+/// completion counts and latency over a recent window, lazy-cache hit
+/// rates and the open-document count. For plugin dashboards and for
+/// backing up performance complaints with numbers.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metrics {
+    /// Keymap entries contributed by each loaded layer, keyed by the
+    /// source name (`embedded`, `families`, or a file path).
+    pub entries_per_source: std::collections::HashMap<String, usize>,
+    /// Completion requests answered with a candidate list.
+    pub completions: u64,
+    /// Mean time spent answering them, in microseconds.
+    pub completion_latency_avg_us: u64,
+    /// 95th-percentile time over the same window, in microseconds.
+    pub completion_latency_p95_us: u64,
+    /// Hits and misses across the lazily built caches (per-language and
+    /// per-folder keymaps, fuzzy index, flat trie).
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub open_documents: usize,
+}
+
+/// `aim/status`: the server's current input state, returned by the request
+/// of this name and pushed as a notification whenever the active keymap
+/// changes (profile switches, hot reloads). Statusbar material: "loaded
+/// `entries` entries from `files` files", with `message` carrying whatever
+/// went wrong during the last rebuild.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub profile: Option<String>,
+    pub entries: usize,
+    #[serde(default)]
+    pub files: usize,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+pub enum StatusNotification {}
+
+impl tower_lsp::lsp_types::notification::Notification for StatusNotification {
+    type Params = Status;
+    const METHOD: &'static str = "aim/status";
+}
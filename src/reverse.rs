@@ -0,0 +1,59 @@
+//! Reverse index from output symbols back to the key sequences that produce
+//! them. Symbols are also indexed under their NFC/NFD/NFKC forms, so a
+//! character found in a document matches even when the document stores a
+//! different (de)composition than the keymap.
+
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Default)]
+pub struct ReverseIndex {
+    map: HashMap<String, Vec<String>>,
+}
+
+impl ReverseIndex {
+    pub fn new(entries: &[(String, String)]) -> Self {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for (seq, sym) in entries {
+            let forms = [
+                sym.clone(),
+                sym.nfc().collect(),
+                sym.nfd().collect(),
+                sym.nfkc().collect(),
+            ];
+            for form in forms {
+                let seqs = map.entry(form).or_default();
+                if !seqs.contains(seq) {
+                    seqs.push(seq.clone());
+                }
+            }
+        }
+        ReverseIndex { map }
+    }
+
+    /// All sequences producing `sym` or something canonically or
+    /// compatibility-equivalent to it.
+    pub fn lookup(&self, sym: &str) -> Vec<String> {
+        self.map
+            .get(sym)
+            .or_else(|| self.map.get(&sym.nfc().collect::<String>()))
+            .or_else(|| self.map.get(&sym.nfkc().collect::<String>()))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalized_reverse_lookup() {
+        let entries = vec![("'e".to_string(), "é".to_string())];
+        let index = ReverseIndex::new(&entries);
+        assert_eq!(index.lookup("é"), vec!["'e"]);
+        // decomposed form: e followed by combining acute
+        assert_eq!(index.lookup("e\u{301}"), vec!["'e"]);
+        assert!(index.lookup("x").is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Locally collected usage statistics: how often each sequence actually got
+//! expanded. Exportable so users can prune their keymaps or share ranking
+//! seeds between machines.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct UsageStats {
+    counts: DashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsExport {
+    pub counts: BTreeMap<String, u64>,
+    pub never_used: Vec<String>,
+}
+
+impl UsageStats {
+    pub fn record(&self, seq: &str) {
+        *self.counts.entry(seq.to_string()).or_insert(0) += 1;
+    }
+
+    /// Read persisted counts (a plain JSON map) back in; a missing or
+    /// unreadable file just starts every count from zero.
+    pub fn load(path: &Path) -> Self {
+        let stats = UsageStats::default();
+        if let Ok(raw) = std::fs::read_to_string(path)
+            && let Ok(counts) = serde_json::from_str::<BTreeMap<String, u64>>(&raw)
+        {
+            for (seq, n) in counts {
+                stats.counts.insert(seq, n);
+            }
+        }
+        stats
+    }
+
+    /// Persist the counts, so frequently used symbols keep their ranking
+    /// across sessions.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let counts: BTreeMap<String, u64> = self
+            .counts
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        std::fs::write(
+            path,
+            serde_json::to_string_pretty(&counts).unwrap_or_default(),
+        )
+    }
+
+    /// How often `seq` has been expanded so far.
+    pub fn count(&self, seq: &str) -> u64 {
+        self.counts.get(seq).map(|c| *c).unwrap_or(0)
+    }
+
+    /// Snapshot of the counts plus every keymap sequence never expanded.
+    pub fn export(&self, entries: &[(String, String)]) -> StatsExport {
+        let counts: BTreeMap<String, u64> = self
+            .counts
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        let mut never_used: Vec<String> = entries
+            .iter()
+            .map(|(seq, _)| seq.clone())
+            .filter(|seq| !counts.contains_key(seq))
+            .collect();
+        never_used.sort();
+        never_used.dedup();
+        StatsExport { counts, never_used }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let stats = UsageStats::default();
+        stats.record("to");
+        stats.record("to");
+        let entries = vec![
+            ("to".to_string(), "→".to_string()),
+            ("Gl-".to_string(), "ƛ".to_string()),
+        ];
+        let export = stats.export(&entries);
+        assert_eq!(export.counts.get("to"), Some(&2));
+        assert_eq!(export.never_used, vec!["Gl-"]);
+    }
+
+    #[test]
+    fn test_save_load_round_trip() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join("aim-lsp-stats-test/stats.json");
+        let stats = UsageStats::default();
+        stats.record("to");
+        stats.record("to");
+        stats.record("forall");
+        stats.save(&path)?;
+        let reloaded = UsageStats::load(&path);
+        assert_eq!(reloaded.count("to"), 2);
+        assert_eq!(reloaded.count("forall"), 1);
+        assert_eq!(reloaded.count("never"), 0);
+        Ok(())
+    }
+}
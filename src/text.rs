@@ -0,0 +1,232 @@
+//! Position arithmetic over document text: mapping LSP `Position`s onto byte
+//! offsets and splicing incremental content changes into the stored copy.
+//! Columns are counted in the negotiated [`Encoding`].
+
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+/// The position encoding agreed on during `initialize`. UTF-16 is the
+/// protocol default; clients like Neovim prefer UTF-8 offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    pub fn from_lsp(kind: &PositionEncodingKind) -> Option<Self> {
+        match kind.as_str() {
+            "utf-8" => Some(Encoding::Utf8),
+            "utf-16" => Some(Encoding::Utf16),
+            "utf-32" => Some(Encoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn to_lsp(self) -> PositionEncodingKind {
+        match self {
+            Encoding::Utf8 => PositionEncodingKind::UTF8,
+            Encoding::Utf16 => PositionEncodingKind::UTF16,
+            Encoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// How many columns `c` spans in this encoding.
+    fn units(self, c: char) -> usize {
+        match self {
+            Encoding::Utf8 => c.len_utf8(),
+            Encoding::Utf16 => c.len_utf16(),
+            Encoding::Utf32 => 1,
+        }
+    }
+}
+
+/// Byte offset of `position` in `text`, with the column counted in `enc`
+/// units. Past-the-end positions clamp to the end of their line, as servers
+/// are expected to tolerate.
+pub fn byte_offset(text: &str, position: Position, enc: Encoding) -> usize {
+    let mut offset = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if i == position.line as usize {
+            let mut units = 0;
+            for (at, c) in line.char_indices() {
+                if units >= position.character as usize || c == '\n' || c == '\r' {
+                    return offset + at;
+                }
+                units += enc.units(c);
+            }
+            return offset + line.len();
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// How many columns `s` spans in `enc` units.
+pub fn width(s: &str, enc: Encoding) -> usize {
+    s.chars().map(|c| enc.units(c)).sum()
+}
+
+/// Convert a char index within `line` into a column in `enc` units.
+pub fn col(line: &str, char_idx: usize, enc: Encoding) -> u32 {
+    line.chars()
+        .take(char_idx)
+        .map(|c| enc.units(c))
+        .sum::<usize>() as u32
+}
+
+/// The char index whose span covers column `character` (counted in `enc`
+/// units) of `line`; past-the-end columns clamp to the char count.
+pub fn char_index(line: &str, character: usize, enc: Encoding) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        let w = enc.units(c);
+        if character < units + w {
+            return i;
+        }
+        units += w;
+    }
+    line.chars().count()
+}
+
+/// The character whose span covers column `character` (counted in `enc`
+/// units) of `line`.
+pub fn char_at(line: &str, character: usize, enc: Encoding) -> Option<char> {
+    line.chars().nth(char_index(line, character, enc))
+}
+
+/// The part of `position`'s line before the cursor. Slicing by the
+/// negotiated column unit keeps prefixes correct on lines that already
+/// contain multi-unit symbols, where a char count would drift.
+pub fn before_cursor(text: &str, position: Position, enc: Encoding) -> Option<&str> {
+    let line = text.lines().nth(position.line as usize)?;
+    Some(prefix_of(line, position.character, enc))
+}
+
+/// The part of `line` left of the cursor column, counted in `enc` units;
+/// the whole line when the cursor sits at or past its end.
+pub fn prefix_of(line: &str, character: u32, enc: Encoding) -> &str {
+    let mut units = 0;
+    for (at, c) in line.char_indices() {
+        if units >= character as usize {
+            return &line[..at];
+        }
+        units += enc.units(c);
+    }
+    line
+}
+
+/// Byte offsets of every line start, so pulling one line out of a document
+/// is a slice instead of a scan from the top. Rebuilt whenever the stored
+/// document changes; completion fires on nearly every printable character,
+/// and `lines().nth()` from offset zero adds up in large files.
+#[derive(Debug)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        for (at, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                starts.push(at + 1);
+            }
+        }
+        LineIndex { starts }
+    }
+
+    /// The text of `line` without its terminator, exactly what
+    /// `text.lines().nth(line)` would have scanned for.
+    pub fn line<'a>(&self, text: &'a str, line: usize) -> Option<&'a str> {
+        let start = *self.starts.get(line)?;
+        if start >= text.len() {
+            return None;
+        }
+        let end = self.starts.get(line + 1).copied().unwrap_or(text.len());
+        let raw = &text[start..end];
+        Some(match raw.strip_suffix('\n') {
+            Some(r) => r.strip_suffix('\r').unwrap_or(r),
+            None => raw,
+        })
+    }
+}
+
+/// Apply one LSP content change: ranged changes splice into the old text,
+/// rangeless ones replace the whole document.
+pub fn apply_change(text: &str, range: Option<Range>, new_text: &str, enc: Encoding) -> String {
+    match range {
+        Some(range) => {
+            let start = byte_offset(text, range.start, enc);
+            let end = byte_offset(text, range.end, enc).max(start);
+            let mut out = String::with_capacity(text.len() - (end - start) + new_text.len());
+            out.push_str(&text[..start]);
+            out.push_str(new_text);
+            out.push_str(&text[end..]);
+            out
+        }
+        None => new_text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        for text in ["", "one", "a\nbb\nccc", "crlf\r\nlast", "trail\n", "a\n\nb"] {
+            let index = LineIndex::new(text);
+            for i in 0..4 {
+                assert_eq!(index.line(text, i), text.lines().nth(i), "{:?}:{}", text, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_byte_offset() {
+        let text = "λx\nαβ→\n";
+        // columns are utf-16 units by default, offsets bytes
+        assert_eq!(byte_offset(text, Position::new(0, 1), Encoding::Utf16), 2);
+        assert_eq!(byte_offset(text, Position::new(1, 2), Encoding::Utf16), 8);
+        // a utf-8 client addresses the same spot as column 4
+        assert_eq!(byte_offset(text, Position::new(1, 4), Encoding::Utf8), 8);
+        assert_eq!(byte_offset(text, Position::new(1, 2), Encoding::Utf32), 8);
+        // past the end of a line clamps before the newline
+        assert_eq!(byte_offset(text, Position::new(0, 99), Encoding::Utf16), 3);
+        assert_eq!(
+            byte_offset(text, Position::new(9, 0), Encoding::Utf16),
+            text.len()
+        );
+    }
+
+    #[test]
+    fn test_crlf() {
+        let text = "ab\r\ncd\r\n";
+        // the trailing \r never counts as part of the line
+        assert_eq!(before_cursor(text, Position::new(0, 9), Encoding::Utf16), Some("ab"));
+        assert_eq!(byte_offset(text, Position::new(0, 9), Encoding::Utf16), 2);
+        assert_eq!(byte_offset(text, Position::new(1, 1), Encoding::Utf16), 5);
+        let spliced = apply_change(
+            text,
+            Some(Range::new(Position::new(1, 0), Position::new(1, 1))),
+            "X",
+            Encoding::Utf16,
+        );
+        assert_eq!(spliced, "ab\r\nXd\r\n");
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let text = "id : ∀ A\n";
+        let spliced = apply_change(
+            text,
+            Some(Range::new(Position::new(0, 5), Position::new(0, 6))),
+            "\\forall",
+            Encoding::Utf16,
+        );
+        assert_eq!(spliced, "id : \\forall A\n");
+        assert_eq!(apply_change(text, None, "x", Encoding::Utf16), "x");
+    }
+}
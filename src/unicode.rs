@@ -0,0 +1,163 @@
+//! Character metadata sourced from embedded Unicode data, used to document
+//! completion candidates so users can pick the semantically right symbol.
+
+use std::sync::OnceLock;
+
+use unicode_general_category::{GeneralCategory, get_general_category};
+
+/// Two-letter general category abbreviation (e.g. `Sm`, `Ll`).
+pub fn category(c: char) -> &'static str {
+    use GeneralCategory::*;
+    match get_general_category(c) {
+        UppercaseLetter => "Lu",
+        LowercaseLetter => "Ll",
+        TitlecaseLetter => "Lt",
+        ModifierLetter => "Lm",
+        OtherLetter => "Lo",
+        NonspacingMark => "Mn",
+        SpacingMark => "Mc",
+        EnclosingMark => "Me",
+        DecimalNumber => "Nd",
+        LetterNumber => "Nl",
+        OtherNumber => "No",
+        ConnectorPunctuation => "Pc",
+        DashPunctuation => "Pd",
+        OpenPunctuation => "Ps",
+        ClosePunctuation => "Pe",
+        InitialPunctuation => "Pi",
+        FinalPunctuation => "Pf",
+        OtherPunctuation => "Po",
+        MathSymbol => "Sm",
+        ModifierSymbol => "Sk",
+        CurrencySymbol => "Sc",
+        OtherSymbol => "So",
+        SpaceSeparator => "Zs",
+        LineSeparator => "Zl",
+        ParagraphSeparator => "Zp",
+        Control => "Cc",
+        Format => "Cf",
+        Surrogate => "Cs",
+        PrivateUse => "Co",
+        _ => "Cn",
+    }
+}
+
+/// Whether `c` is a combining mark — a character that attaches to the one
+/// before it instead of standing on its own.
+pub fn is_combining(c: char) -> bool {
+    matches!(
+        get_general_category(c),
+        GeneralCategory::NonspacingMark
+            | GeneralCategory::SpacingMark
+            | GeneralCategory::EnclosingMark
+    )
+}
+
+pub fn block(c: char) -> &'static str {
+    unicode_blocks::find_unicode_block(c)
+        .map(|b| b.name())
+        .unwrap_or("Unknown Block")
+}
+
+/// Official Unicode character name, falling back to the code point.
+pub fn name(c: char) -> String {
+    unicode_names2::name(c)
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| format!("U+{:04X}", c as u32))
+}
+
+/// `U+XXXX` code points of every character in `sym`.
+pub fn codepoints(sym: &str) -> String {
+    sym.chars()
+        .map(|c| format!("U+{:04X}", c as u32))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Unicode names of every character in `sym`.
+pub fn names(sym: &str) -> String {
+    sym.chars().map(name).collect::<Vec<_>>().join(", ")
+}
+
+/// Lowercased official names of every assigned character through the
+/// supplementary planes that carry named symbols, built once on first use —
+/// the name-search fallback is opt-in and many sessions never pay for it.
+fn names_table() -> &'static [(String, char)] {
+    static TABLE: OnceLock<Vec<(String, char)>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0u32..=0x2FFFF)
+            .filter_map(char::from_u32)
+            .filter_map(|c| unicode_names2::name(c).map(|n| (n.to_string().to_lowercase(), c)))
+            .collect()
+    })
+}
+
+/// Characters whose official Unicode name contains every word of `query`
+/// (words split on hyphens, underscores or spaces, case-insensitive),
+/// whole-name matches first so `snowman` answers ☃ before its variants.
+/// Makes any character reachable by name even when no keymap names it.
+pub fn search_names(query: &str, limit: usize) -> Vec<char> {
+    let q = query.to_lowercase();
+    let words: Vec<&str> = q.split(['-', '_', ' ']).filter(|w| !w.is_empty()).collect();
+    if words.is_empty() {
+        return vec![];
+    }
+    let joined = words.join(" ");
+    let mut ret: Vec<char> = names_table()
+        .iter()
+        .filter(|(name, _)| *name == joined)
+        .map(|(_, c)| *c)
+        .collect();
+    for (name, c) in names_table() {
+        if ret.len() >= limit {
+            break;
+        }
+        if words.iter().all(|w| name.contains(w)) && !ret.contains(c) {
+            ret.push(*c);
+        }
+    }
+    ret.truncate(limit);
+    ret
+}
+
+/// One line per character: the glyph itself, code point, official name,
+/// block and general category — enough to tell ε from ϵ before inserting.
+pub fn describe(sym: &str) -> String {
+    sym.chars()
+        .map(|c| {
+            format!(
+                "{} U+{:04X} {} · {}, {}",
+                c,
+                c as u32,
+                name(c),
+                block(c),
+                category(c)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_names() {
+        // an exact name comes before every longer name containing it
+        assert_eq!(search_names("snowman", 3)[0], '☃');
+        assert_eq!(search_names("black-star", 3)[0], '★');
+        // every query word must appear; order of words doesn't matter
+        assert!(search_names("star black", 3).contains(&'★'));
+        assert!(search_names("", 3).is_empty());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("→"), "→ U+2192 RIGHTWARDS ARROW · Arrows, Sm");
+        assert_eq!(
+            describe("λ"),
+            "λ U+03BB GREEK SMALL LETTER LAMDA · Greek and Coptic, Ll"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Embedded cross-reference table mapping symbols to their LaTeX command
+//! and HTML entity equivalents, for authors moving between formats.
+
+const TABLE: &[(&str, &str, &str)] = &[
+    ("α", "\\alpha", "&alpha;"),
+    ("β", "\\beta", "&beta;"),
+    ("γ", "\\gamma", "&gamma;"),
+    ("δ", "\\delta", "&delta;"),
+    ("ε", "\\varepsilon", "&epsilon;"),
+    ("λ", "\\lambda", "&lambda;"),
+    ("μ", "\\mu", "&mu;"),
+    ("π", "\\pi", "&pi;"),
+    ("σ", "\\sigma", "&sigma;"),
+    ("φ", "\\varphi", "&phi;"),
+    ("ω", "\\omega", "&omega;"),
+    ("Γ", "\\Gamma", "&Gamma;"),
+    ("Δ", "\\Delta", "&Delta;"),
+    ("Λ", "\\Lambda", "&Lambda;"),
+    ("Π", "\\Pi", "&Pi;"),
+    ("Σ", "\\Sigma", "&Sigma;"),
+    ("Ω", "\\Omega", "&Omega;"),
+    ("→", "\\rightarrow", "&rarr;"),
+    ("←", "\\leftarrow", "&larr;"),
+    ("↔", "\\leftrightarrow", "&harr;"),
+    ("⇒", "\\Rightarrow", "&rArr;"),
+    ("⇐", "\\Leftarrow", "&lArr;"),
+    ("∀", "\\forall", "&forall;"),
+    ("∃", "\\exists", "&exist;"),
+    ("¬", "\\neg", "&not;"),
+    ("∧", "\\wedge", "&and;"),
+    ("∨", "\\vee", "&or;"),
+    ("∩", "\\cap", "&cap;"),
+    ("∪", "\\cup", "&cup;"),
+    ("∈", "\\in", "&isin;"),
+    ("∉", "\\notin", "&notin;"),
+    ("⊆", "\\subseteq", "&sube;"),
+    ("⊇", "\\supseteq", "&supe;"),
+    ("∅", "\\emptyset", "&empty;"),
+    ("∞", "\\infty", "&infin;"),
+    ("≤", "\\leq", "&le;"),
+    ("≥", "\\geq", "&ge;"),
+    ("≠", "\\neq", "&ne;"),
+    ("≡", "\\equiv", "&equiv;"),
+    ("≈", "\\approx", "&asymp;"),
+    ("±", "\\pm", "&plusmn;"),
+    ("×", "\\times", "&times;"),
+    ("÷", "\\div", "&divide;"),
+    ("·", "\\cdot", "&middot;"),
+    ("∘", "\\circ", "&compfn;"),
+    ("√", "\\sqrt", "&radic;"),
+    ("∑", "\\sum", "&sum;"),
+    ("∏", "\\prod", "&prod;"),
+    ("∫", "\\int", "&int;"),
+    ("∂", "\\partial", "&part;"),
+    ("∇", "\\nabla", "&nabla;"),
+    ("⊥", "\\bot", "&perp;"),
+    ("⊤", "\\top", "&top;"),
+    ("⊢", "\\vdash", "&vdash;"),
+    ("⊨", "\\models", "&vDash;"),
+    ("ℕ", "\\mathbb{N}", "&Nopf;"),
+    ("ℤ", "\\mathbb{Z}", "&Zopf;"),
+    ("ℚ", "\\mathbb{Q}", "&Qopf;"),
+    ("ℝ", "\\mathbb{R}", "&Ropf;"),
+    ("ℂ", "\\mathbb{C}", "&Copf;"),
+];
+
+pub fn find(sym: &str) -> Option<(&'static str, &'static str)> {
+    TABLE
+        .iter()
+        .find(|(s, _, _)| *s == sym)
+        .map(|(_, latex, html)| (*latex, *html))
+}
+
+/// A documentation line like `LaTeX: \lambda · HTML: &lambda;`, when the
+/// symbol is in the table.
+pub fn describe(sym: &str) -> Option<String> {
+    find(sym).map(|(latex, html)| format!("LaTeX: {} · HTML: {}", latex, html))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_xref() {
+        assert_eq!(find("λ"), Some(("\\lambda", "&lambda;")));
+        assert_eq!(describe("ƛ"), None);
+    }
+}